@@ -4,7 +4,6 @@
 /// Print the largest sum of the calories carried by a single elf.
 /// Part B:
 /// Prints the sum of the calories carried by the 3 largest calorie carrying single elves.
-
 fn main() {
     let input = include_str!("../../assets/day01.txt");
 
@@ -12,7 +12,7 @@ fn main() {
     let start_state = lines
         .chunks(2)
         .map(|info| (parse_stacks(info[0]), parse_moves(info[1])))
-        .last()
+        .next_back()
         .unwrap();
 
     let part_a_end = run_moves(&start_state);
@@ -5,7 +5,6 @@ use std::collections::HashSet;
 /// Print char count up to the end of the 4 unique chars.
 /// Part B:
 /// Print char count up to the end of the 14 unique chars.
-
 fn detect_unique(input: &str, unique_count: usize) {
     let start_idx = input
         .chars()
@@ -110,8 +110,7 @@ fn find_sum(root: Rc<RefCell<Dir>>) -> u64 {
     let mut stack = vec![root];
     let mut sum = 0;
 
-    while !stack.is_empty() {
-        let dir = stack.pop().unwrap();
+    while let Some(dir) = stack.pop() {
         stack.append(&mut dir.borrow_mut().dirs.clone());
 
         if dir.borrow_mut().size() < 100000 {
@@ -129,8 +128,7 @@ fn find_smallest_to_free_size(root: Rc<RefCell<Dir>>) -> u64 {
     let mut stack = vec![root];
     let mut best = u64::MAX;
 
-    while !stack.is_empty() {
-        let dir = stack.pop().unwrap();
+    while let Some(dir) = stack.pop() {
         stack.append(&mut dir.borrow_mut().dirs.clone());
 
         if dir.borrow().size() >= needed && dir.borrow().size() < best {
@@ -7,8 +7,7 @@ use std::collections::HashSet;
 /// Find the highest scenic score in the grid. A scenic score
 /// is how many trees you can see from that point (including the blocking tree)
 /// in each direction multiplied together.
-
-fn find_visible(grid: &Vec<Vec<i64>>) {
+fn find_visible(grid: &[Vec<i64>]) {
     let mut set = HashSet::new();
 
     for (row_idx, row) in grid.iter().enumerate() {
@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 /// Input is a series of operations.
 /// noop takes one cycle
 /// addx adds to the register x (starts a 1), takes 2 cycles.
@@ -8,7 +10,6 @@
 /// A screen is 40 pixels wide and 6 deep. It is rendered rtl ttb, once pixel per cycle.
 /// The horizontal center ps of the item being rendered is in the register and it is 3 pixels wide.
 /// Render the screen and find the drawn letters.
-
 struct Op {
     add: i32,
     end_cycle: i32,
@@ -27,7 +28,7 @@ fn load_ops(input: &str) -> Vec<Op> {
             cycle += 1;
         } else {
             ops.push(Op {
-                add: line.split(' ').last().unwrap().parse::<i32>().unwrap(),
+                add: line.split(' ').next_back().unwrap().parse::<i32>().unwrap(),
                 end_cycle: cycle + 1,
             });
             cycle += 2;
@@ -37,7 +38,7 @@ fn load_ops(input: &str) -> Vec<Op> {
     ops
 }
 
-fn sum_cycles_of_interest(ops: &[Op]) {
+fn sum_cycles_of_interest(ops: &[Op]) -> i32 {
     let mut reg = 1;
     let mut sum = 0;
 
@@ -55,38 +56,266 @@ fn sum_cycles_of_interest(ops: &[Op]) {
         reg += op.add;
     }
 
-    println!("At end reg {}, sum {}", reg, sum);
+    sum
 }
 
-fn render_screen(ops: &[Op]) {
-    let mut op_idx = 0;
-    let mut reg = 1;
+/// The CRT screen, drawn one pixel per cycle left to right, top to bottom.
+struct Framebuffer {
+    width: usize,
+    height: usize,
+    sprite_width: usize,
+    pixels: Vec<Vec<bool>>,
+}
 
-    for cycle in 0..240 {
-        let px = (cycle % 40) + 1;
-        if reg == px || reg + 1 == px || reg + 2 == px {
-            print!("#");
-        } else {
-            print!(".");
+impl Framebuffer {
+    fn new(width: usize, height: usize, sprite_width: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            sprite_width,
+            pixels: vec![vec![false; width]; height],
         }
+    }
+
+    /// Runs the ops, drawing a pixel per cycle. on_cycle sees the screen after each pixel is drawn.
+    fn render(&mut self, ops: &[Op], mut on_cycle: impl FnMut(usize, &Framebuffer)) {
+        let mut op_idx = 0;
+        let mut reg = 1;
+
+        for cycle in 0..self.width * self.height {
+            // The register holds the center of the sprite
+            let col = (cycle % self.width) as i32;
+            let sprite_start = reg - (self.sprite_width as i32 / 2);
+            if col >= sprite_start && col < sprite_start + self.sprite_width as i32 {
+                self.pixels[cycle / self.width][cycle % self.width] = true;
+            }
 
-        if (cycle + 1) % 40 == 0 {
-            println!();
+            on_cycle(cycle, self);
+
+            if let Some(op) = ops.get(op_idx) {
+                if cycle as i32 == op.end_cycle {
+                    reg += op.add;
+                    op_idx += 1;
+                }
+            }
         }
+    }
+
+    /// Plain PBM image, each screen pixel drawn as a scale x scale block.
+    fn to_pbm(&self, scale: usize) -> String {
+        let mut out = format!("P1\n{} {}\n", self.width * scale, self.height * scale);
+
+        for row in &self.pixels {
+            let line = row
+                .iter()
+                .flat_map(|lit| std::iter::repeat_n(if *lit { "1" } else { "0" }, scale))
+                .collect::<Vec<_>>()
+                .join(" ");
 
-        if cycle == ops[op_idx].end_cycle {
-            reg += ops[op_idx].add;
-            op_idx += 1;
+            for _ in 0..scale {
+                out.push_str(&line);
+                out.push('\n');
+            }
         }
+
+        out
     }
-    println!();
 }
 
+impl Display for Framebuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for row in &self.pixels {
+            for lit in row {
+                write!(f, "{}", if *lit { '#' } else { '.' })?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Letters in the standard AoC font are 4 pixels wide and 6 high, followed by a 1 pixel gap.
+const GLYPH_STRIDE: usize = 5;
+
+const GLYPHS: [(char, [&str; 6]); 18] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+    (' ', ["....", "....", "....", "....", "....", "...."]),
+];
+
+/// The glyphs on the screen that did not match any known letter, by index from the left.
+#[derive(Debug)]
+struct UnknownGlyphs {
+    positions: Vec<usize>,
+}
+
+impl Display for UnknownGlyphs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let positions = self
+            .positions
+            .iter()
+            .map(|pos| pos.to_string())
+            .collect::<Vec<_>>();
+        write!(
+            f,
+            "unrecognised glyphs at positions {}",
+            positions.join(", ")
+        )
+    }
+}
+
+/// Reads the letters drawn on the screen, one glyph per 5 columns.
+fn read_screen(screen: &[Vec<bool>]) -> Result<String, UnknownGlyphs> {
+    let width = screen.first().map_or(0, |row| row.len());
+    let mut letters = String::new();
+    let mut unknown = vec![];
+
+    for (glyph_idx, start_col) in (0..width).step_by(GLYPH_STRIDE).enumerate() {
+        let found = GLYPHS.iter().find(|(_, rows)| {
+            screen.len() == rows.len()
+                && screen.iter().zip(rows).all(|(screen_row, glyph_row)| {
+                    glyph_row.chars().enumerate().all(|(offset, px)| {
+                        screen_row.get(start_col + offset).copied().unwrap_or(false) == (px == '#')
+                    })
+                })
+        });
+
+        match found {
+            Some((letter, _)) => letters.push(*letter),
+            None => unknown.push(glyph_idx),
+        }
+    }
+
+    if !unknown.is_empty() {
+        return Err(UnknownGlyphs { positions: unknown });
+    }
+
+    Ok(letters.trim_end().to_string())
+}
+
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|idx| args.get(idx + 1))
+        .cloned()
+}
+
+/// Options:
+/// --json                  print the answers as JSON
+/// --width/--height N      screen size, defaults to 40x6
+/// --sprite N              sprite width, defaults to 3
+/// --image PATH            save the final screen as a PBM image
+/// --frames DIR            save a PBM image of the screen after every cycle
+/// --scale N               pixel size of saved images, defaults to 8
 fn main() {
     let input = include_str!("../../assets/day10.txt");
     let ops = load_ops(input);
 
-    sum_cycles_of_interest(&ops);
+    let args = std::env::args().collect::<Vec<_>>();
+    let json = args.iter().any(|arg| arg == "--json");
+    let num_arg = |name: &str, default: usize| {
+        arg_value(&args, name).map_or(default, |val| val.parse::<usize>().unwrap())
+    };
+    let scale = num_arg("--scale", 8);
+    let frames_dir = arg_value(&args, "--frames");
+
+    let sum = sum_cycles_of_interest(&ops);
+
+    let mut screen = Framebuffer::new(
+        num_arg("--width", 40),
+        num_arg("--height", 6),
+        num_arg("--sprite", 3),
+    );
+    if let Some(dir) = &frames_dir {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+    screen.render(&ops, |cycle, screen| {
+        if let Some(dir) = &frames_dir {
+            let path = format!("{}/frame_{:04}.pbm", dir, cycle);
+            std::fs::write(path, screen.to_pbm(scale)).unwrap();
+        }
+    });
+
+    if let Some(path) = arg_value(&args, "--image") {
+        std::fs::write(path, screen.to_pbm(scale)).unwrap();
+    }
+
+    let letters = read_screen(&screen.pixels);
 
-    render_screen(&ops);
+    if json {
+        let part_b = match &letters {
+            Ok(letters) => format!("\"{}\"", letters),
+            Err(_) => "null".to_string(),
+        };
+        println!("{{\"part_a\": {}, \"part_b\": {}}}", sum, part_b);
+    } else {
+        println!("Sum of strengths {}", sum);
+        println!("{}", screen);
+    }
+
+    match letters {
+        Ok(letters) => {
+            if !json {
+                println!("Letters {}", letters);
+            }
+        }
+        Err(err) => eprintln!("{}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_screen(rows: &[&str]) -> Vec<Vec<bool>> {
+        rows.iter()
+            .map(|row| row.chars().map(|px| px == '#').collect())
+            .collect()
+    }
+
+    #[test]
+    fn reads_letters() {
+        let screen = parse_screen(&[
+            "###....##.####.###..#..#.###..####.#..#.",
+            "#..#....#.#....#..#.#..#.#..#.#....#..#.",
+            "###.....#.###..#..#.####.#..#.###..#..#.",
+            "#..#....#.#....###..#..#.###..#....#..#.",
+            "#..#.#..#.#....#.#..#..#.#.#..#....#..#.",
+            "###...##..#....#..#.#..#.#..#.#.....##..",
+        ]);
+
+        assert_eq!(read_screen(&screen).unwrap(), "BJFRHRFU");
+    }
+
+    #[test]
+    fn reports_unknown_glyphs() {
+        let screen = parse_screen(&[
+            "###..#..#.####.",
+            "#..#.##.#.#....",
+            "###..#.##.###..",
+            "#..#.#..#.#....",
+            "#..#.#..#.#....",
+            "###..#..#.####.",
+        ]);
+
+        let err = read_screen(&screen).unwrap_err();
+        assert_eq!(err.positions, vec![1]);
+        assert_eq!(err.to_string(), "unrecognised glyphs at positions 1");
+    }
 }
@@ -7,64 +7,328 @@
 /// Calculate the monkey business after 20 rounds.
 /// Part B.
 /// Calculate the monkey business after 10000 rounds.
+use std::{collections::HashMap, fmt::Display, str::FromStr};
 
+/// The right hand side of a monkey's operation, e.g. `old * (old + 3)`.
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Old,
+    Const(u128),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Evaluates the expression, reducing every intermediate value by the modulus if there is one.
+    /// Dividing a reduced value loses what the reduction took off, so expressions with division
+    /// are refused a modulus. Without a modulus, subtraction stops at 0 and overflow is an error.
+    fn eval(&self, old: u128, modulus: Option<u128>) -> Result<u128, String> {
+        if modulus.is_some() && self.has_div() {
+            return Err(format!(
+                "Can't reduce the worry in '{}' as it divides",
+                self
+            ));
+        }
+        let reduce = |val: Option<u128>| {
+            val.map(|val| modulus.map_or(val, |modulus| val % modulus))
+                .ok_or(format!("Worry overflowed in '{}'", self))
+        };
+
+        match self {
+            Expr::Old => reduce(Some(old)),
+            Expr::Const(val) => reduce(Some(*val)),
+            Expr::Add(lhs, rhs) => {
+                reduce(lhs.eval(old, modulus)?.checked_add(rhs.eval(old, modulus)?))
+            }
+            Expr::Sub(lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(old, modulus)?, rhs.eval(old, modulus)?);
+                match modulus {
+                    Some(modulus) => Ok((lhs + modulus - rhs) % modulus),
+                    None => Ok(lhs.saturating_sub(rhs)),
+                }
+            }
+            Expr::Mul(lhs, rhs) => {
+                reduce(lhs.eval(old, modulus)?.checked_mul(rhs.eval(old, modulus)?))
+            }
+            Expr::Div(lhs, rhs) => lhs
+                .eval(old, modulus)?
+                .checked_div(rhs.eval(old, modulus)?)
+                .ok_or(format!("Division by zero in '{}'", self)),
+        }
+    }
+
+    fn has_div(&self) -> bool {
+        match self {
+            Expr::Old | Expr::Const(_) => false,
+            Expr::Div(_, _) => true,
+            Expr::Add(lhs, rhs) | Expr::Sub(lhs, rhs) | Expr::Mul(lhs, rhs) => {
+                lhs.has_div() || rhs.has_div()
+            }
+        }
+    }
+}
+
+impl Expr {
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Add(_, _) | Expr::Sub(_, _) => 1,
+            Expr::Mul(_, _) | Expr::Div(_, _) => 2,
+            Expr::Old | Expr::Const(_) => 3,
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (lhs, op, rhs) = match self {
+            Expr::Old => return write!(f, "old"),
+            Expr::Const(val) => return write!(f, "{}", val),
+            Expr::Add(lhs, rhs) => (lhs, '+', rhs),
+            Expr::Sub(lhs, rhs) => (lhs, '-', rhs),
+            Expr::Mul(lhs, rhs) => (lhs, '*', rhs),
+            Expr::Div(lhs, rhs) => (lhs, '/', rhs),
+        };
+
+        // Operators are left associative, so a right hand side of equal precedence needs brackets
+        if lhs.precedence() < self.precedence() {
+            write!(f, "({})", lhs)?;
+        } else {
+            write!(f, "{}", lhs)?;
+        }
+        write!(f, " {} ", op)?;
+        if rhs.precedence() <= self.precedence() {
+            write!(f, "({})", rhs)
+        } else {
+            write!(f, "{}", rhs)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Old,
+    Num(u128),
+    Op(char),
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = input.chars().peekable();
+
+    while let Some(&ch) = chars.peek() {
+        match ch {
+            ' ' => {
+                chars.next();
+            }
+            '+' | '-' | '*' | '/' => {
+                tokens.push(Token::Op(ch));
+                chars.next();
+            }
+            '(' => {
+                tokens.push(Token::Open);
+                chars.next();
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                chars.next();
+            }
+            '0'..='9' => {
+                let mut val = 0;
+                while let Some(digit) = chars.peek().and_then(|ch| ch.to_digit(10)) {
+                    val = val * 10 + digit as u128;
+                    chars.next();
+                }
+                tokens.push(Token::Num(val));
+            }
+            'a'..='z' => {
+                let mut word = String::new();
+                while let Some(&ch) = chars.peek().filter(|ch| ch.is_ascii_lowercase()) {
+                    word.push(ch);
+                    chars.next();
+                }
+                if word != "old" {
+                    return Err(format!("Unknown name '{}'", word));
+                }
+                tokens.push(Token::Old);
+            }
+            _ => return Err(format!("Unexpected character '{}'", ch)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens, * and / bind tighter than + and -.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+
+        while let Some(Token::Op(op @ ('+' | '-'))) = self.peek().cloned() {
+            self.pos += 1;
+            let rhs = self.term()?;
+            lhs = match op {
+                '+' => Expr::Add(Box::new(lhs), Box::new(rhs)),
+                _ => Expr::Sub(Box::new(lhs), Box::new(rhs)),
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.factor()?;
+
+        while let Some(Token::Op(op @ ('*' | '/'))) = self.peek().cloned() {
+            self.pos += 1;
+            let rhs = self.factor()?;
+            lhs = match op {
+                '*' => Expr::Mul(Box::new(lhs), Box::new(rhs)),
+                _ => Expr::Div(Box::new(lhs), Box::new(rhs)),
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    fn factor(&mut self) -> Result<Expr, String> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+
+        match token {
+            Some(Token::Old) => Ok(Expr::Old),
+            Some(Token::Num(val)) => Ok(Expr::Const(val)),
+            Some(Token::Open) => {
+                let inner = self.expr()?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(inner)
+                    }
+                    _ => Err("Missing closing bracket".to_string()),
+                }
+            }
+            Some(token) => Err(format!("Unexpected {:?}", token)),
+            None => Err("Unexpected end of operation".to_string()),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Expr, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+        };
+
+        let expr = parser.expr()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected {:?} after operation", token));
+        }
+
+        Ok(expr)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 struct Monkey {
+    id: usize,
     items: Vec<u128>,
-    op: Box<dyn Fn(u128) -> u128>,
-    test: Box<dyn Fn(u128) -> usize>,
+    op: Expr,
+    div: u128,
+    true_monkey: usize,
+    false_monkey: usize,
+}
+
+impl Monkey {
+    fn inspect(&self, item: u128, modulus: Option<u128>) -> Result<u128, String> {
+        self.op
+            .eval(item, modulus)
+            .map_err(|err| format!("Monkey {}: {}", self.id, err))
+    }
+
+    fn throw_to(&self, item: u128) -> usize {
+        if item.is_multiple_of(self.div) {
+            return self.true_monkey;
+        }
+
+        self.false_monkey
+    }
+}
+
+impl Display for Monkey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let items = self
+            .items
+            .iter()
+            .map(|item| item.to_string())
+            .collect::<Vec<_>>();
+
+        writeln!(f, "Monkey {}:", self.id)?;
+        writeln!(f, "  Starting items: {}", items.join(", "))?;
+        writeln!(f, "  Operation: new = {}", self.op)?;
+        writeln!(f, "  Test: divisible by {}", self.div)?;
+        writeln!(f, "    If true: throw to monkey {}", self.true_monkey)?;
+        write!(f, "    If false: throw to monkey {}", self.false_monkey)
+    }
 }
 
 fn load(input: &str) -> (Vec<Monkey>, u128) {
-    let mut lcm = 1;
     let monkeys = input
+        .trim_end()
         .split("\n\n")
         .map(|monkey| monkey.lines().map(String::from).collect::<Vec<_>>())
         .map(|monkey| {
+            let id = monkey[0]
+                .trim_start_matches("Monkey ")
+                .trim_end_matches(':')
+                .parse::<usize>()
+                .unwrap();
+
             // Items
             let items = monkey[1]
                 .split(':')
-                .last()
+                .next_back()
                 .unwrap()
                 .split(',')
-                .map(|item| item.trim().parse::<u128>().unwrap())
+                .map(|item| item.trim())
+                .filter(|item| !item.is_empty())
+                .map(|item| item.parse::<u128>().unwrap())
                 .collect::<Vec<_>>();
 
             // Operation
-            let op: Box<dyn Fn(u128) -> u128>;
-            {
-                let op_parts = monkey[2]
-                    .split("new = old")
-                    .last()
-                    .unwrap()
-                    .trim()
-                    .split(' ')
-                    .map(String::from)
-                    .collect::<Vec<_>>();
-
-                op = match (op_parts[0].as_str(), op_parts[1].as_str()) {
-                    ("*", "old") => Box::new(move |old: u128| old * old),
-                    ("+", "old") => Box::new(move |old: u128| old + old),
-                    ("*", _val) => {
-                        Box::new(move |old: u128| old * op_parts[1].parse::<u128>().unwrap())
-                    }
-                    ("+", _val) => {
-                        Box::new(move |old: u128| old + op_parts[1].parse::<u128>().unwrap())
-                    }
-                    _ => unreachable!("Invalid operation"),
-                };
-            }
+            let op = monkey[2]
+                .split("new =")
+                .last()
+                .unwrap()
+                .parse::<Expr>()
+                .unwrap();
 
             // Test
             let true_monkey = monkey[4]
                 .split(' ')
-                .last()
+                .next_back()
                 .unwrap()
                 .trim()
                 .parse::<usize>()
                 .unwrap();
             let false_monkey = monkey[5]
                 .split(' ')
-                .last()
+                .next_back()
                 .unwrap()
                 .trim()
                 .parse::<usize>()
@@ -77,71 +341,290 @@ fn load(input: &str) -> (Vec<Monkey>, u128) {
                 .parse::<u128>()
                 .unwrap();
 
-            lcm *= div;
-            let test = Box::new(move |val: u128| {
-                if val % div == 0 {
-                    return true_monkey;
-                }
-
-                false_monkey
-            });
-
-            Monkey { items, op, test }
+            Monkey {
+                id,
+                items,
+                op,
+                div,
+                true_monkey,
+                false_monkey,
+            }
         })
         .collect::<Vec<_>>();
 
+    let lcm = monkeys.iter().map(|monkey| monkey.div).product();
+
     (monkeys, lcm)
 }
 
-fn calc_monkey_business(mut monkeys: Vec<Monkey>, rounds: usize, div: u128, lcm: u128) {
-    let mut inspections: Vec<u128> = vec![0; monkeys.len()];
+/// The monkeys in the input notation, which load reads back.
+fn snapshot(monkeys: &[Monkey]) -> String {
+    monkeys
+        .iter()
+        .map(|monkey| monkey.to_string())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// The results of running the monkeys round by round.
+struct Simulation {
+    /// Inspections by each monkey, per round.
+    history: Vec<Vec<u128>>,
+    /// The monkeys that inspected each item, in order. Items are numbered by their starting order.
+    journeys: Vec<Vec<usize>>,
+    /// The monkeys and the items they hold after the last round.
+    monkeys: Vec<Monkey>,
+}
+
+impl Simulation {
+    fn inspections(&self) -> Vec<u128> {
+        let mut totals = vec![0; self.history.first().map_or(0, |round| round.len())];
+        for round in &self.history {
+            for (total, count) in totals.iter_mut().zip(round) {
+                *total += count;
+            }
+        }
+
+        totals
+    }
+}
+
+fn simulate(
+    mut monkeys: Vec<Monkey>,
+    rounds: usize,
+    div: u128,
+    lcm: u128,
+) -> Result<Simulation, String> {
+    // Reducing by the lcm keeps the tests intact, but only if nothing is divided after the reduction
+    let reducible = monkeys.iter().all(|monkey| !monkey.op.has_div());
+    let modulus = if div == 1 && reducible {
+        Some(lcm)
+    } else {
+        None
+    };
+
+    // Tag every item with an id so its journey can be followed
+    let mut held = monkeys
+        .iter()
+        .map(|monkey| monkey.items.len())
+        .scan(0, |next_id, count| {
+            *next_id += count;
+            Some((*next_id - count..*next_id).collect::<Vec<_>>())
+        })
+        .collect::<Vec<_>>();
+    let mut journeys = vec![vec![]; held.iter().map(|ids| ids.len()).sum()];
+    let mut history = vec![];
 
     for _round in 0..rounds {
+        let mut inspections: Vec<u128> = vec![0; monkeys.len()];
+
         for monkey_idx in 0..monkeys.len() {
-            let items;
+            let mut items;
+            let ids;
             {
                 let monkey = &mut monkeys[monkey_idx];
+                items = std::mem::take(&mut monkey.items);
+                ids = std::mem::take(&mut held[monkey_idx]);
 
-                for item in &mut monkey.items {
+                for item in &mut items {
                     {
                         // Inspect
-                        *item = monkey.op.as_ref()(*item);
+                        *item = monkey.inspect(*item, modulus)?;
                         inspections[monkey_idx] += 1;
 
                         // Worry
                         *item /= div;
 
-                        *item %= lcm;
+                        if let Some(modulus) = modulus {
+                            *item %= modulus;
+                        }
                     }
                 }
-
-                items = monkey.items.clone();
-                monkey.items = vec![];
             }
 
-            for item in items {
+            for (item, id) in items.into_iter().zip(ids) {
+                journeys[id].push(monkey_idx);
+
                 let monkey = &mut monkeys[monkey_idx];
-                let new_monkey_idx = monkey.test.as_ref()(item);
+                let new_monkey_idx = monkey.throw_to(item);
                 let new_monkey = &mut monkeys[new_monkey_idx];
                 new_monkey.items.push(item);
+                held[new_monkey_idx].push(id);
+            }
+        }
+
+        history.push(inspections);
+    }
+
+    Ok(Simulation {
+        history,
+        journeys,
+        monkeys,
+    })
+}
+
+/// The rounds of a single item, which repeat once it is back at the same monkey with the same worry.
+struct ItemCycle {
+    /// The monkeys that inspected the item in each round, up to the end of the first cycle.
+    rounds: Vec<Vec<usize>>,
+    /// The round the cycle starts.
+    start: usize,
+}
+
+impl ItemCycle {
+    /// Inspections by each monkey of this item over the given rounds.
+    fn inspections(&self, monkey_count: usize, rounds: u128) -> Vec<u128> {
+        let mut totals = vec![0; monkey_count];
+        let count_rounds = |totals: &mut Vec<u128>, range: std::ops::Range<usize>, times: u128| {
+            for round in &self.rounds[range] {
+                for monkey_idx in round {
+                    totals[*monkey_idx] += times;
+                }
+            }
+        };
+
+        let prefix = (rounds as usize).min(self.start);
+        count_rounds(&mut totals, 0..prefix, 1);
+
+        let remaining = rounds - prefix as u128;
+        let len = (self.rounds.len() - self.start) as u128;
+        count_rounds(&mut totals, self.start..self.rounds.len(), remaining / len);
+        count_rounds(
+            &mut totals,
+            self.start..self.start + (remaining % len) as usize,
+            1,
+        );
+
+        totals
+    }
+}
+
+/// Follows a single item, starting at the given monkey, until it repeats a round.
+/// Items never affect each other, and with the worry reduced by the lcm there are a finite number
+/// of states an item can be in at the start of a round. Operations that divide can't be reduced.
+fn find_item_cycle(
+    monkeys: &[Monkey],
+    mut monkey_idx: usize,
+    mut item: u128,
+    lcm: u128,
+) -> Result<ItemCycle, String> {
+    let mut seen = HashMap::new();
+    let mut rounds = vec![];
+
+    loop {
+        if let Some(start) = seen.insert((monkey_idx, item), rounds.len()) {
+            return Ok(ItemCycle { rounds, start });
+        }
+
+        // Throws to later monkeys are handled in the same round
+        let mut visits = vec![];
+        loop {
+            let monkey = &monkeys[monkey_idx];
+            item = monkey.inspect(item, Some(lcm))?;
+            visits.push(monkey_idx);
+
+            let new_monkey_idx = monkey.throw_to(item);
+            let same_round = new_monkey_idx > monkey_idx;
+            monkey_idx = new_monkey_idx;
+            if !same_round {
+                break;
+            }
+        }
+        rounds.push(visits);
+    }
+}
+
+/// Inspection counts after any number of rounds, with no worry division.
+fn inspections_by_cycles(monkeys: &[Monkey], rounds: u128, lcm: u128) -> Result<Vec<u128>, String> {
+    let mut totals = vec![0; monkeys.len()];
+
+    for (monkey_idx, monkey) in monkeys.iter().enumerate() {
+        for item in &monkey.items {
+            let cycle = find_item_cycle(monkeys, monkey_idx, *item, lcm)?;
+            for (total, count) in totals
+                .iter_mut()
+                .zip(cycle.inspections(monkeys.len(), rounds))
+            {
+                *total += count;
             }
         }
     }
 
+    Ok(totals)
+}
+
+fn monkey_business(mut inspections: Vec<u128>) -> u128 {
     inspections.sort_unstable();
     inspections.reverse();
-    dbg!(&inspections);
 
-    let monkey_business = inspections[0] * inspections[1];
-    println!("Monkey business: {}", monkey_business);
+    inspections[0] * inspections[1]
+}
+
+fn calc_monkey_business(monkeys: Vec<Monkey>, rounds: usize, div: u128, lcm: u128) {
+    let sim = match simulate(monkeys, rounds, div, lcm) {
+        Ok(sim) => sim,
+        Err(err) => return println!("Can't simulate {} rounds: {}", rounds, err),
+    };
+
+    let inspections = sim.inspections();
+    println!("Inspections: {:?}", inspections);
+
+    let longest = sim
+        .journeys
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, journey)| journey.len())
+        .unwrap();
+    println!(
+        "Most inspected item {} ({} inspections)",
+        longest.0,
+        longest.1.len()
+    );
+
+    println!("Monkey business: {}", monkey_business(inspections));
 }
 
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|idx| args.get(idx + 1))
+        .cloned()
+}
+
+/// Options:
+/// --rounds N      rounds to find the monkey business after using item cycles, defaults to 10^12
+/// --snapshot N    print the monkeys after N rounds of part B, in the input notation
 fn main() {
     let input = include_str!("../../assets/day11.txt");
+    let args = std::env::args().collect::<Vec<_>>();
 
     let part_a = load(input);
     calc_monkey_business(part_a.0, 20, 3, part_a.1);
 
     let part_b = load(input);
     calc_monkey_business(part_b.0, 10000, 1, part_b.1);
+
+    let rounds =
+        arg_value(&args, "--rounds").map_or(1_000_000_000_000, |arg| arg.parse::<u128>().unwrap());
+    let (monkeys, lcm) = load(input);
+    match inspections_by_cycles(&monkeys, rounds, lcm) {
+        Ok(inspections) => println!(
+            "Monkey business after {} rounds: {}",
+            rounds,
+            monkey_business(inspections)
+        ),
+        Err(err) => println!("Can't find item cycles: {}", err),
+    }
+
+    if let Some(rounds) = arg_value(&args, "--snapshot") {
+        let (monkeys, lcm) = load(input);
+        match simulate(monkeys, rounds.parse::<usize>().unwrap(), 1, lcm) {
+            Ok(sim) => {
+                let snapshot = snapshot(&sim.monkeys);
+                assert!(load(&snapshot).0 == sim.monkeys, "Snapshot did not reload");
+                println!("{}", snapshot);
+            }
+            Err(err) => println!("Can't simulate {} rounds: {}", rounds, err),
+        }
+    }
 }
@@ -1,4 +1,7 @@
-use std::collections::HashSet;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashSet},
+};
 
 /// Input is heightmap where a is lowest and z is highest.
 /// S indicates start and has height a.
@@ -8,10 +11,9 @@ use std::collections::HashSet;
 /// What is the shortest number of steps to get from start to end.
 /// Part B:
 /// What is the shortest path from any a elevation to E.
-
 type IsEndFn = Box<dyn Fn((usize, usize), &[Vec<char>]) -> bool>;
 
-fn in_bounds(grid: &Vec<Vec<char>>, pos: (usize, usize)) -> bool {
+fn in_bounds(grid: &[Vec<char>], pos: (usize, usize)) -> bool {
     if pos.0 >= grid.len() {
         return false;
     }
@@ -23,24 +25,93 @@ fn in_bounds(grid: &Vec<Vec<char>>, pos: (usize, usize)) -> bool {
     true
 }
 
-fn distance(grid: &[Vec<char>], from: (usize, usize), to: (usize, usize), forwards: bool) -> i32 {
+/// The cost of stepping between two heights, None if the step isn't allowed.
+type CostFn = Box<dyn Fn(char, char) -> Option<i32>>;
+
+/// How you are allowed to move over the heightmap.
+struct Rules {
+    cost: CostFn,
+    diagonal: bool,
+}
+
+impl Rules {
+    /// Climb at most max_climb in a step, any number lower. Descending costs descent_cost extra.
+    /// The search can't take a step that costs less than 0, so those rules are an error.
+    fn new(max_climb: i32, descent_cost: i32, diagonal: bool) -> Result<Rules, String> {
+        if 1 + descent_cost < 0 {
+            return Err(format!(
+                "A descent cost of {} makes stepping down cost less than 0",
+                descent_cost
+            ));
+        }
+
+        Ok(Rules {
+            cost: Box::new(move |from, to| {
+                let jump = to as i32 - from as i32;
+                if jump > max_climb {
+                    return None;
+                }
+
+                if jump < 0 {
+                    return Some(1 + descent_cost);
+                }
+
+                Some(1)
+            }),
+            diagonal,
+        })
+    }
+}
+
+/// The cost to step from one square to the next. When searching backwards the step is reversed.
+fn distance(
+    grid: &[Vec<char>],
+    rules: &Rules,
+    from: (usize, usize),
+    to: (usize, usize),
+    forwards: bool,
+) -> Option<i32> {
     let from_height = grid[from.0][from.1];
     let to_height = grid[to.0][to.1];
 
-    let jump = to_height as i32 - from_height as i32;
-
     if forwards {
-        if jump > 1 {
-            return i32::MAX;
+        rules.cost.as_ref()(from_height, to_height)
+    } else {
+        rules.cost.as_ref()(to_height, from_height)
+    }
+}
+
+fn neighbours(grid: &[Vec<char>], pos: (usize, usize), diagonal: bool) -> Vec<(usize, usize)> {
+    let mut dirs = vec![];
+    for row_step in -1..=1_i64 {
+        for col_step in -1..=1_i64 {
+            let is_diagonal = row_step != 0 && col_step != 0;
+            if (row_step, col_step) == (0, 0) || (is_diagonal && !diagonal) {
+                continue;
+            }
+
+            let row = pos.0 as i64 + row_step;
+            let col = pos.1 as i64 + col_step;
+            if row >= 0 && col >= 0 {
+                dirs.push((row as usize, col as usize));
+            }
         }
-    } else if jump < -1 {
-        return i32::MAX;
     }
 
-    1
+    dirs.into_iter()
+        .filter(|dir| in_bounds(grid, *dir))
+        .collect()
 }
 
-fn dijkstra(grid: &Vec<Vec<char>>, start: (usize, usize), is_end: IsEndFn, forwards: bool) -> i32 {
+/// Finds the shortest path, returned as the positions from start to end inclusive.
+/// None if no end can be reached.
+fn dijkstra(
+    grid: &[Vec<char>],
+    rules: &Rules,
+    start: (usize, usize),
+    is_end: IsEndFn,
+    forwards: bool,
+) -> Option<Vec<(usize, usize)>> {
     let mut unvisited = HashSet::new();
     for (row_idx, row) in grid.iter().enumerate() {
         for col_idx in 0..row.len() {
@@ -49,30 +120,28 @@ fn dijkstra(grid: &Vec<Vec<char>>, start: (usize, usize), is_end: IsEndFn, forwa
     }
 
     let mut distances = vec![vec![i32::MAX; grid[0].len()]; grid.len()];
+    let mut previous = vec![vec![None; grid[0].len()]; grid.len()];
     distances[start.0][start.1] = 0;
 
     let mut current = start;
     loop {
-        let mut dirs = vec![];
-        if current.0 != 0 {
-            dirs.push((current.0 - 1, current.1));
-        }
-        dirs.push((current.0 + 1, current.1));
-        if current.1 != 0 {
-            dirs.push((current.0, current.1 - 1));
+        // With steps of different costs a square is only finished once it is the closest unvisited
+        if is_end.as_ref()(current, grid) {
+            let mut path = vec![current];
+            while let Some(prev) = previous[path.last().unwrap().0][path.last().unwrap().1] {
+                path.push(prev);
+            }
+            path.reverse();
+
+            return Some(path);
         }
-        dirs.push((current.0, current.1 + 1));
-
-        for dir in dirs {
-            if in_bounds(grid, dir) {
-                let mut dist = distance(grid, current, dir, forwards);
-                if dist != i32::MAX {
-                    dist += distances[current.0][current.1];
-                    distances[dir.0][dir.1] = distances[dir.0][dir.1].min(dist);
-
-                    if is_end.as_ref()(dir, grid) {
-                        return distances[dir.0][dir.1];
-                    }
+
+        for dir in neighbours(grid, current, rules.diagonal) {
+            if let Some(dist) = distance(grid, rules, current, dir, forwards) {
+                let dist = dist + distances[current.0][current.1];
+                if dist < distances[dir.0][dir.1] {
+                    distances[dir.0][dir.1] = dist;
+                    previous[dir.0][dir.1] = Some(current);
                 }
             }
         }
@@ -86,12 +155,117 @@ fn dijkstra(grid: &Vec<Vec<char>>, start: (usize, usize), is_end: IsEndFn, forwa
 
         match next {
             Some(n) => current = *n,
-            None => unreachable!("No solution"),
+            None => return None,
+        }
+    }
+}
+
+/// Cost to reach every square from the nearest a, found with one search from all of them.
+/// None where a square can't be reached.
+fn distances_from_lowest(grid: &[Vec<char>], rules: &Rules) -> Vec<Vec<Option<i32>>> {
+    let mut distances = vec![vec![None; grid[0].len()]; grid.len()];
+    let mut queue = BinaryHeap::new();
+
+    for (row_idx, row) in grid.iter().enumerate() {
+        for (col_idx, height) in row.iter().enumerate() {
+            if *height == 'a' {
+                distances[row_idx][col_idx] = Some(0);
+                queue.push(Reverse((0, (row_idx, col_idx))));
+            }
+        }
+    }
+
+    while let Some(Reverse((current_dist, current))) = queue.pop() {
+        if distances[current.0][current.1].is_some_and(|dist| dist < current_dist) {
+            continue;
+        }
+
+        for dir in neighbours(grid, current, rules.diagonal) {
+            if let Some(dist) = distance(grid, rules, current, dir, true) {
+                let dist = current_dist + dist;
+                if distances[dir.0][dir.1].is_none_or(|known| dist < known) {
+                    distances[dir.0][dir.1] = Some(dist);
+                    queue.push(Reverse((dist, dir)));
+                }
+            }
         }
     }
+
+    distances
+}
+
+fn path_cost(grid: &[Vec<char>], rules: &Rules, path: &[(usize, usize)]) -> i32 {
+    path.windows(2)
+        .map(|step| distance(grid, rules, step[0], step[1], true).unwrap())
+        .sum()
+}
+
+/// The heightmap with each step of the path drawn as an arrow towards the next step.
+fn render_path(grid: &[Vec<char>], path: &[(usize, usize)]) -> String {
+    let mut map = grid.to_vec();
+
+    for step in path.windows(2) {
+        let (from, to) = (step[0], step[1]);
+        map[from.0][from.1] = match (to.0 as i64 - from.0 as i64, to.1 as i64 - from.1 as i64) {
+            (-1, 0) => '^',
+            (1, 0) => 'v',
+            (0, -1) => '<',
+            (0, 1) => '>',
+            (-1, -1) => '↖',
+            (-1, 1) => '↗',
+            (1, -1) => '↙',
+            (1, 1) => '↘',
+            _ => unreachable!("Path steps must be adjacent"),
+        };
+    }
+
+    if let Some(end) = path.last() {
+        map[end.0][end.1] = 'E';
+    }
+
+    map.iter()
+        .map(|row| row.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn print_distances(distances: &[Vec<Option<i32>>]) {
+    for row in distances {
+        let row = row
+            .iter()
+            .map(|dist| dist.map_or("   .".to_string(), |dist| format!("{:4}", dist)))
+            .collect::<String>();
+        println!("{}", row);
+    }
 }
 
+fn arg_value(args: &[String], name: &str) -> Option<i32> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|idx| args.get(idx + 1))
+        .map(|val| val.parse::<i32>().unwrap())
+}
+
+/// Options:
+/// --max-climb N       highest step up allowed, defaults to 1
+/// --descent-cost N    extra cost of stepping down, defaults to 0
+/// --diagonal          allow diagonal steps
+/// --distances         print the cost to reach every square from the nearest a
 fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    let rules = Rules::new(
+        arg_value(&args, "--max-climb").unwrap_or(1),
+        arg_value(&args, "--descent-cost").unwrap_or(0),
+        args.iter().any(|arg| arg == "--diagonal"),
+    );
+    let rules = match rules {
+        Ok(rules) => rules,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+
     let input = include_str!("../../assets/day12.txt");
     let mut grid = input
         .lines()
@@ -114,19 +288,52 @@ fn main() {
         }
     }
 
-    let shortest_end = dijkstra(
+    let route = dijkstra(
         &grid,
+        &rules,
         start,
         Box::new(move |pos, _grid| pos.0 == end.0 && pos.1 == end.1),
         true,
     );
-    println!("Shortest to end {}", shortest_end);
+    match route {
+        Some(route) => {
+            println!("{}\n", render_path(&grid, &route));
+            println!(
+                "Shortest to end {} steps, cost {}",
+                route.len() - 1,
+                path_cost(&grid, &rules, &route)
+            );
+        }
+        None => println!("Shortest to end: no route"),
+    }
 
-    let shortest_trail = dijkstra(
+    // Searched backwards from the end, so reverse to draw it uphill
+    let trail = dijkstra(
         &grid,
+        &rules,
         end,
         Box::new(|pos, grid| grid[pos.0][pos.1] == 'a'),
         false,
     );
-    println!("Shortest trail {}", shortest_trail);
+    match trail {
+        Some(mut trail) => {
+            trail.reverse();
+            println!("{}\n", render_path(&grid, &trail));
+            println!(
+                "Shortest trail {} steps, cost {}",
+                trail.len() - 1,
+                path_cost(&grid, &rules, &trail)
+            );
+        }
+        None => println!("Shortest trail: no route"),
+    }
+
+    let distances = distances_from_lowest(&grid, &rules);
+    if args.iter().any(|arg| arg == "--distances") {
+        print_distances(&distances);
+    }
+    match distances[end.0][end.1] {
+        Some(cost) => println!("Cheapest trail from all a {}", cost),
+        None => println!("Cheapest trail from all a: no route"),
+    }
 }
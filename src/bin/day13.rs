@@ -1,4 +1,4 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cmp::Ordering, fmt::Display, str::FromStr, time};
 
 /// Input is pairs of lists that represent a packet.
 /// Pairs are line separated.
@@ -11,142 +11,484 @@ use std::{cell::RefCell, rc::Rc};
 /// Part B:
 /// Sort the packets and insert two extras [[2]] [[6]].
 /// Print the sort indices of the two extra packets multiplied together.
+#[derive(Debug, Clone)]
+enum Packet {
+    Int(u32),
+    List(Vec<Packet>),
+}
 
-#[derive(Debug)]
-enum ListItem {
-    Value(u32),
-    List(Vec<RcListItem>, bool),
+impl Ord for Packet {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Packet::Int(left), Packet::Int(right)) => left.cmp(right),
+            (Packet::Int(left), Packet::List(right)) => {
+                compare_lists(std::slice::from_ref(&Packet::Int(*left)), right)
+            }
+            (Packet::List(left), Packet::Int(right)) => {
+                compare_lists(left, std::slice::from_ref(&Packet::Int(*right)))
+            }
+            (Packet::List(left), Packet::List(right)) => compare_lists(left, right),
+        }
+    }
 }
 
-type RcListItem = Rc<RefCell<ListItem>>;
+/// Compare item by item, the list that runs out of items first is smaller.
+fn compare_lists(left: &[Packet], right: &[Packet]) -> Ordering {
+    for (left, right) in left.iter().zip(right) {
+        match left.cmp(right) {
+            Ordering::Equal => continue,
+            ordering => return ordering,
+        }
+    }
+
+    left.len().cmp(&right.len())
+}
 
-impl ListItem {
-    fn list() -> RcListItem {
-        Rc::new(RefCell::new(ListItem::List(vec![], false)))
+impl PartialOrd for Packet {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
+}
 
-    fn value(value: u32) -> RcListItem {
-        Rc::new(RefCell::new(ListItem::Value(value)))
+/// Packets are equal when the rules can't order them, so 1 and [1] are equal.
+impl PartialEq for Packet {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
+}
+
+impl Eq for Packet {}
 
-    fn add(&mut self, item: RcListItem) {
+impl Display for Packet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ListItem::Value(_) => panic!("Can't add an item to a value"),
-            ListItem::List(ref mut list, _) => list.push(item),
+            Packet::Int(val) => write!(f, "{}", val),
+            Packet::List(items) => {
+                write!(f, "[")?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
         }
     }
+}
 
-    fn last_list(&mut self) -> RcListItem {
-        match self {
-            ListItem::Value(_) => panic!("List isn't a list"),
-            ListItem::List(ref mut list, _) => list.last().unwrap().clone(),
+/// Why a packet couldn't be parsed, pos is the char offset into the line.
+#[derive(Debug, PartialEq)]
+struct ParseError {
+    pos: usize,
+    message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.message, self.pos)
+    }
+}
+
+struct Parser<'a> {
+    chars: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            pos: self.pos,
+            message: message.to_string(),
         }
     }
 
-    fn is_divider(&self) -> bool {
-        match self {
-            ListItem::Value(_) => false,
-            ListItem::List(_, is_divider) => *is_divider,
+    fn packet(&mut self) -> Result<Packet, ParseError> {
+        match self.chars.get(self.pos) {
+            Some(b'[') => self.list(),
+            Some(b'0'..=b'9') => self.int(),
+            Some(_) => Err(self.error("Expected '[' or a number")),
+            None => Err(self.error("Unexpected end of packet")),
         }
     }
 
-    fn parse(s: &str, is_divider: bool) -> RcListItem {
-        let chars = s.chars().collect::<Vec<_>>();
-        let top = Rc::new(RefCell::new(ListItem::List(vec![], is_divider)));
-        let mut stack = vec![top.clone()];
+    fn list(&mut self) -> Result<Packet, ParseError> {
+        // Skip the [
+        self.pos += 1;
+        let mut items = vec![];
 
-        let mut pos: usize = 1;
+        if self.chars.get(self.pos) == Some(&b']') {
+            self.pos += 1;
+            return Ok(Packet::List(items));
+        }
 
-        while pos < chars.len() {
-            let current = stack.last().unwrap().clone();
-            if chars[pos] == '[' {
-                current.borrow_mut().add(ListItem::list());
-                stack.push(current.borrow_mut().last_list());
+        loop {
+            items.push(self.packet()?);
 
-                pos += 1;
-            } else if chars[pos] == ']' {
-                stack.pop();
-                pos += 1;
-            } else if chars[pos] == ',' {
-                pos += 1;
-            } else {
-                let end = chars[pos + 1..]
-                    .iter()
-                    .position(|c| *c == ',' || *c == ']')
-                    .unwrap()
-                    + pos
-                    + 1;
+            match self.chars.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Packet::List(items));
+                }
+                Some(_) => return Err(self.error("Expected ',' or ']'")),
+                None => return Err(self.error("Unclosed list")),
+            }
+        }
+    }
 
-                let x = &chars[pos..end].iter().collect::<String>();
-                let value = x.parse::<u32>().unwrap();
+    fn int(&mut self) -> Result<Packet, ParseError> {
+        let start = self.pos;
+        let mut val: u32 = 0;
 
-                current.borrow_mut().add(ListItem::value(value));
-                pos = end;
-            }
+        while let Some(digit @ b'0'..=b'9') = self.chars.get(self.pos) {
+            val = val
+                .checked_mul(10)
+                .and_then(|val| val.checked_add((digit - b'0') as u32))
+                .ok_or(ParseError {
+                    pos: start,
+                    message: "Number too large".to_string(),
+                })?;
+            self.pos += 1;
         }
 
-        top
+        Ok(Packet::Int(val))
     }
+}
 
-    #[allow(dead_code)]
-    fn to_str(&self) -> String {
-        match self {
-            ListItem::Value(v) => v.to_string(),
-            ListItem::List(l, _) => {
-                let mut s = String::from("[");
-                for i in l.iter() {
-                    s += &i.borrow().to_str();
-                    s += ","
+impl FromStr for Packet {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Packet, Self::Err> {
+        let mut parser = Parser {
+            chars: input.as_bytes(),
+            pos: 0,
+        };
+
+        let packet = parser.packet()?;
+        if parser.pos != input.len() {
+            return Err(parser.error("Unexpected characters after packet"));
+        }
+
+        Ok(packet)
+    }
+}
+
+/// The comparison trace of a pair in the puzzle's own words, and where in each packet it was decided.
+struct Explanation {
+    lines: Vec<String>,
+    ordering: Ordering,
+    /// Indices into the left and right packets of the items that decided the order
+    left_path: Vec<usize>,
+    right_path: Vec<usize>,
+}
+
+struct Explainer {
+    lines: Vec<String>,
+    left_path: Vec<usize>,
+    right_path: Vec<usize>,
+    decided: Option<(Vec<usize>, Vec<usize>)>,
+}
+
+impl Explainer {
+    fn say(&mut self, depth: usize, line: String) {
+        self.lines.push(format!("{}- {}", "  ".repeat(depth), line));
+    }
+
+    fn decide(&mut self, depth: usize, ordering: Ordering, reason: &str) -> Ordering {
+        let verdict = match ordering {
+            Ordering::Less => "so inputs are in the right order",
+            _ => "so inputs are not in the right order",
+        };
+        self.say(depth, format!("{}, {}", reason, verdict));
+        self.decided = Some((self.left_path.clone(), self.right_path.clone()));
+
+        ordering
+    }
+
+    fn compare(&mut self, left: &Packet, right: &Packet, depth: usize) -> Ordering {
+        self.say(depth, format!("Compare {} vs {}", left, right));
+
+        match (left, right) {
+            (Packet::Int(lv), Packet::Int(rv)) => match lv.cmp(rv) {
+                Ordering::Less => self.decide(depth + 1, Ordering::Less, "Left side is smaller"),
+                Ordering::Greater => {
+                    self.decide(depth + 1, Ordering::Greater, "Right side is smaller")
                 }
-                s += "]";
-                s
+                Ordering::Equal => Ordering::Equal,
+            },
+            (Packet::Int(lv), Packet::List(rl)) => {
+                self.say(
+                    depth + 1,
+                    format!("Mixed types; convert left to [{}] and retry comparison", lv),
+                );
+                self.say(depth + 1, format!("Compare [{}] vs {}", lv, right));
+                self.compare_lists(&[Packet::Int(*lv)], rl, (true, false), depth + 2)
+            }
+            (Packet::List(ll), Packet::Int(rv)) => {
+                self.say(
+                    depth + 1,
+                    format!(
+                        "Mixed types; convert right to [{}] and retry comparison",
+                        rv
+                    ),
+                );
+                self.say(depth + 1, format!("Compare {} vs [{}]", left, rv));
+                self.compare_lists(ll, &[Packet::Int(*rv)], (false, true), depth + 2)
+            }
+            (Packet::List(ll), Packet::List(rl)) => {
+                self.compare_lists(ll, rl, (false, false), depth + 1)
+            }
+        }
+    }
+
+    /// Converted ints aren't really lists, so their items don't add to the path.
+    fn compare_lists(
+        &mut self,
+        left: &[Packet],
+        right: &[Packet],
+        converted: (bool, bool),
+        depth: usize,
+    ) -> Ordering {
+        for (idx, (lv, rv)) in left.iter().zip(right).enumerate() {
+            if !converted.0 {
+                self.left_path.push(idx);
             }
+            if !converted.1 {
+                self.right_path.push(idx);
+            }
+
+            let ordering = self.compare(lv, rv, depth);
+
+            if !converted.0 {
+                self.left_path.pop();
+            }
+            if !converted.1 {
+                self.right_path.pop();
+            }
+
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        match left.len().cmp(&right.len()) {
+            Ordering::Less => self.decide(depth, Ordering::Less, "Left side ran out of items"),
+            Ordering::Greater => {
+                self.decide(depth, Ordering::Greater, "Right side ran out of items")
+            }
+            Ordering::Equal => Ordering::Equal,
+        }
+    }
+}
+
+fn explain(left: &Packet, right: &Packet) -> Explanation {
+    let mut explainer = Explainer {
+        lines: vec![],
+        left_path: vec![],
+        right_path: vec![],
+        decided: None,
+    };
+
+    let ordering = explainer.compare(left, right, 0);
+    let (left_path, right_path) = explainer.decided.unwrap_or_default();
+
+    Explanation {
+        lines: explainer.lines,
+        ordering,
+        left_path,
+        right_path,
+    }
+}
+
+/// The packet with the item at path marked as >item<.
+fn highlight(packet: &Packet, path: &[usize]) -> String {
+    match (packet, path.split_first()) {
+        (_, None) => format!(">{}<", packet),
+        (Packet::List(items), Some((idx, rest))) => {
+            let items = items
+                .iter()
+                .enumerate()
+                .map(|(item_idx, item)| {
+                    if item_idx == *idx {
+                        highlight(item, rest)
+                    } else {
+                        item.to_string()
+                    }
+                })
+                .collect::<Vec<_>>();
+            format!("[{}]", items.join(","))
         }
+        (Packet::Int(_), Some(_)) => unreachable!("Path goes inside an int"),
     }
 }
 
-fn ordering(left: RcListItem, right: RcListItem) -> std::cmp::Ordering {
-    match (&*left.borrow(), &*right.borrow()) {
-        (ListItem::Value(lv), ListItem::Value(rv)) => lv.cmp(rv),
-        (ListItem::Value(lv), ListItem::List(_, _)) => {
-            let wrapper = Rc::new(RefCell::new(ListItem::List(
-                vec![ListItem::value(*lv)],
-                false,
-            )));
-            ordering(wrapper, right.clone())
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Int(u32),
+}
+
+/// Reads tokens straight from the packet text. Ints that are being compared against a list are
+/// wrapped in place by handing them out again, followed by the missing closing brackets.
+struct Cursor<'a> {
+    chars: &'a [u8],
+    pos: usize,
+    /// An int to hand out again as it has been wrapped in a list
+    wrapped: Option<u32>,
+    /// Closing brackets to hand out after the wrapped int
+    wraps: usize,
+    /// Closing brackets still to hand out
+    closes: usize,
+}
+
+impl Cursor<'_> {
+    fn new(packet: &str) -> Cursor<'_> {
+        Cursor {
+            chars: packet.as_bytes(),
+            pos: 0,
+            wrapped: None,
+            wraps: 0,
+            closes: 0,
         }
-        (ListItem::List(_, _), ListItem::Value(rv)) => {
-            let wrapper = Rc::new(RefCell::new(ListItem::List(
-                vec![ListItem::value(*rv)],
-                false,
-            )));
-            ordering(left.clone(), wrapper)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        if let Some(val) = self.wrapped.take() {
+            self.closes = self.wraps;
+            self.wraps = 0;
+            return Some(Token::Int(val));
+        }
+
+        if self.closes > 0 {
+            self.closes -= 1;
+            return Some(Token::Close);
         }
-        (ListItem::List(ll, _), ListItem::List(rl, _)) => {
-            for idx in 0..ll.len() {
-                // Right side ran out of items
-                if idx >= rl.len() {
-                    return std::cmp::Ordering::Greater;
-                }
 
-                match ordering(ll[idx].clone(), rl[idx].clone()) {
-                    std::cmp::Ordering::Less => return std::cmp::Ordering::Less,
-                    std::cmp::Ordering::Equal => continue,
-                    std::cmp::Ordering::Greater => return std::cmp::Ordering::Greater,
+        while self.chars.get(self.pos) == Some(&b',') {
+            self.pos += 1;
+        }
+
+        let token = match self.chars.get(self.pos)? {
+            b'[' => Token::Open,
+            b']' => Token::Close,
+            b'0'..=b'9' => {
+                let mut val = 0;
+                while let Some(digit @ b'0'..=b'9') = self.chars.get(self.pos) {
+                    val = val * 10 + (digit - b'0') as u32;
+                    self.pos += 1;
                 }
+                return Some(Token::Int(val));
             }
+            byte => panic!("Unexpected '{}' at {} in packet", *byte as char, self.pos),
+        };
+        self.pos += 1;
+
+        Some(token)
+    }
 
-            // Left side ran out of items
-            if ll.len() < rl.len() {
-                return std::cmp::Ordering::Less;
+    /// Treat the int just read as the only item in a list, whose opening bracket has been matched.
+    fn wrap(&mut self, val: u32) {
+        self.wrapped = Some(val);
+        self.wraps = self.closes + 1;
+        self.closes = 0;
+    }
+}
+
+/// Compares two packets without parsing them, walking both texts a token at a time.
+fn compare_str(left: &str, right: &str) -> Ordering {
+    let mut left = Cursor::new(left);
+    let mut right = Cursor::new(right);
+
+    loop {
+        match (left.next(), right.next()) {
+            (Some(Token::Int(lv)), Some(Token::Int(rv))) => {
+                if lv != rv {
+                    return lv.cmp(&rv);
+                }
             }
+            (Some(Token::Open), Some(Token::Open)) | (Some(Token::Close), Some(Token::Close)) => {}
+            (Some(Token::Int(lv)), Some(Token::Open)) => left.wrap(lv),
+            (Some(Token::Open), Some(Token::Int(rv))) => right.wrap(rv),
+            // One side ran out of items
+            (Some(Token::Close), _) => return Ordering::Less,
+            (_, Some(Token::Close)) => return Ordering::Greater,
+            (None, None) => return Ordering::Equal,
+            (None, _) => return Ordering::Less,
+            (_, None) => return Ordering::Greater,
+        }
+    }
+}
+
+/// Xorshift, enough to make repeatable benchmark packets.
+struct Rng(u64);
 
-            // Inconclusive
-            std::cmp::Ordering::Equal
+impl Rng {
+    fn next(&mut self, max: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % max
+    }
+}
+
+fn gen_packet(rng: &mut Rng, depth: usize, out: &mut String) {
+    if depth == 0 || rng.next(4) == 0 {
+        out.push_str(&rng.next(11).to_string());
+        return;
+    }
+
+    out.push('[');
+    for idx in 0..rng.next(4) {
+        if idx > 0 {
+            out.push(',');
         }
+        gen_packet(rng, depth - 1, out);
     }
+    out.push(']');
 }
 
+/// Times comparing generated pairs of deeply nested packets by parsing them to trees and by
+/// streaming over the text.
+fn bench(pairs: usize) {
+    let mut rng = Rng(0x2022_1213);
+    let packets = (0..pairs * 2)
+        .map(|_| {
+            let mut packet = String::from("[");
+            gen_packet(&mut rng, 12, &mut packet);
+            packet.push(']');
+            packet
+        })
+        .collect::<Vec<_>>();
+
+    let start = time::SystemTime::now();
+    let tree_ordered = packets
+        .chunks(2)
+        .filter(|pair| pair[0].parse::<Packet>().unwrap() <= pair[1].parse::<Packet>().unwrap())
+        .count();
+    let tree_time = time::SystemTime::now().duration_since(start).unwrap();
+
+    let start = time::SystemTime::now();
+    let stream_ordered = packets
+        .chunks(2)
+        .filter(|pair| compare_str(&pair[0], &pair[1]) != Ordering::Greater)
+        .count();
+    let stream_time = time::SystemTime::now().duration_since(start).unwrap();
+
+    assert_eq!(tree_ordered, stream_ordered);
+    println!("{} pairs, {} ordered", pairs, stream_ordered);
+    println!("Parse and compare trees {:?}", tree_time);
+    println!("Streaming compare {:?}", stream_time);
+    println!(
+        "Speedup {:.1}x",
+        tree_time.as_secs_f64() / stream_time.as_secs_f64()
+    );
+}
+
+/// Options:
+/// --explain N  print how pair N (1-indexed) was compared
+/// --bench N    time both comparators on N generated pairs of nested packets
 fn main() {
     let input = include_str!("../../assets/day13.txt");
 
@@ -155,43 +497,145 @@ fn main() {
         .split("\n\n")
         .map(|pair| pair.lines().collect::<Vec<_>>())
         .map(|pair| {
-            (
-                ListItem::parse(pair[0], false),
-                ListItem::parse(pair[1], false),
-            )
+            let parse = |line: &str| {
+                let packet = line
+                    .parse::<Packet>()
+                    .unwrap_or_else(|err| panic!("Invalid packet {}: {}", line, err));
+                debug_assert_eq!(packet.to_string(), line);
+                packet
+            };
+            (parse(pair[0]), parse(pair[1]))
         })
         .collect::<Vec<_>>();
 
-    let indices_sum = packet_pairs
-        .iter()
+    let indices_sum = input
+        .split("\n\n")
+        .map(|pair| pair.lines().collect::<Vec<_>>())
         .enumerate()
-        .map(|(idx, pair)| (idx + 1, ordering(pair.0.clone(), pair.1.clone())))
-        .filter(|pair| pair.1 != std::cmp::Ordering::Greater)
-        .map(|pair| pair.0)
+        .filter(|(_, pair)| compare_str(pair[0], pair[1]) != Ordering::Greater)
+        .map(|(idx, _)| idx + 1)
         .sum::<usize>();
 
     println!("Indices sum of correctly ordered packets {}", indices_sum);
 
     // Sort the packets and add dividers
-    let div_a = ListItem::parse("[[2]]", true);
-    let div_b = ListItem::parse("[[6]]", true);
+    let div_a = "[[2]]".parse::<Packet>().unwrap();
+    let div_b = "[[6]]".parse::<Packet>().unwrap();
 
     let mut packets = packet_pairs
         .iter()
-        .flat_map(|pair| [pair.0.clone(), pair.1.clone()])
+        .flat_map(|pair| [(pair.0.clone(), false), (pair.1.clone(), false)])
         .collect::<Vec<_>>();
-    packets.push(div_a);
-    packets.push(div_b);
+    packets.push((div_a, true));
+    packets.push((div_b, true));
 
-    packets.sort_by(|a, b| ordering(a.clone(), b.clone()));
+    packets.sort_by(|a, b| a.0.cmp(&b.0));
 
     let divider_sum = packets
         .iter()
         .enumerate()
         .map(|p| (p.0 + 1, p.1))
-        .filter(|p| p.1.borrow().is_divider())
+        .filter(|p| p.1 .1)
         .map(|p| p.0)
         .product::<usize>();
 
     println!("Divider indices sum {}", divider_sum);
+
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(idx) = args.iter().position(|arg| arg == "--explain") {
+        let pair_idx = args[idx + 1].parse::<usize>().unwrap();
+        let pair = &packet_pairs[pair_idx - 1];
+        let explanation = explain(&pair.0, &pair.1);
+
+        println!();
+        println!("== Pair {} ==", pair_idx);
+        for line in &explanation.lines {
+            println!("{}", line);
+        }
+        if explanation.ordering == Ordering::Equal {
+            println!("Packets are equal, so inputs are in the right order");
+        } else {
+            println!(
+                "Decided at left {:?}, right {:?}",
+                explanation.left_path, explanation.right_path
+            );
+            println!("{}", highlight(&pair.0, &explanation.left_path));
+            println!("{}", highlight(&pair.1, &explanation.right_path));
+        }
+    }
+
+    if let Some(idx) = args.iter().position(|arg| arg == "--bench") {
+        println!();
+        bench(args[idx + 1].parse::<usize>().unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "[1,1,3,1,1]
+[1,1,5,1,1]
+
+[[1],[2,3,4]]
+[[1],4]
+
+[9]
+[[8,7,6]]
+
+[[4,4],4,4]
+[[4,4],4,4,4]
+
+[7,7,7,7]
+[7,7,7]
+
+[]
+[3]
+
+[[[]]]
+[[]]
+
+[1,[2,[3,[4,[5,6,7]]]],8,9]
+[1,[2,[3,[4,[5,6,0]]]],8,9]";
+
+    fn tree_cmp(left: &str, right: &str) -> Ordering {
+        left.parse::<Packet>()
+            .unwrap()
+            .cmp(&right.parse::<Packet>().unwrap())
+    }
+
+    #[test]
+    fn comparators_agree() {
+        let mut packets = EXAMPLE
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.to_string())
+            .collect::<Vec<_>>();
+        let mut rng = Rng(0x2022_1213);
+        for _ in 0..200 {
+            let mut packet = String::from("[");
+            gen_packet(&mut rng, 4, &mut packet);
+            packet.push(']');
+            packets.push(packet);
+        }
+
+        // Every packet against every other, itself and itself wrapped in a list
+        for left in &packets {
+            for right in packets.iter().chain([&format!("[{}]", left)]) {
+                assert_eq!(
+                    compare_str(left, right),
+                    tree_cmp(left, right),
+                    "{} vs {}",
+                    left,
+                    right
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Unexpected ' ' at 3 in packet")]
+    fn streaming_rejects_unexpected_bytes() {
+        compare_str("[1, 2]", "[1, 2]");
+    }
 }
@@ -1,3 +1,5 @@
+use cave::{Cave, Floor, Particle, Source};
+
 /// The input describes x,y coords of bits of rock that sand can rest on.
 /// The source of the sand is at 500,0.
 /// Sand tries to fall down, then diagonally left, then diagonally right. Then it comes to rest.
@@ -8,7 +10,7 @@
 /// There is now a floor 2 units below the lowest rock. How many units of sand until
 /// the source is blocked.
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 struct Pos {
     row: i32,
     col: i32,
@@ -66,106 +68,430 @@ fn load_seams(input: &str) -> (Vec<Vec<Pos>>, Pos) {
     (seams, Pos::new(max_row, max_col))
 }
 
-/// Build the grid, '.'=empty, '#'=rock
-fn build_grid(seams: &Vec<Vec<Pos>>, max_pos: &Pos) -> Vec<Vec<char>> {
-    let mut grid = vec![vec!['.'; max_pos.col as usize + 500]; max_pos.row as usize + 3];
+/// Simulates particles falling from sources into a cave of rock.
+mod cave {
+    use super::Pos;
+    use std::collections::{HashMap, HashSet};
 
-    // Add the seams
-    for seam in seams {
-        for points in seam.windows(2) {
-            let from = &points[0];
-            let to = &points[1];
+    /// A horizontal rock floor, endless unless limited to a range of columns.
+    pub struct Floor {
+        pub row: i32,
+        pub cols: Option<(i32, i32)>,
+    }
 
-            let diff = Pos::new(
-                (to.row - from.row).clamp(-1, 1),
-                (to.col - from.col).clamp(-1, 1),
-            );
+    impl Floor {
+        fn contains(&self, pos: Pos) -> bool {
+            pos.row == self.row
+                && self
+                    .cols
+                    .is_none_or(|(from, to)| pos.col >= from && pos.col <= to)
+        }
+    }
 
-            let mut current = *from;
-            while current != *to {
-                grid[current.row as usize][current.col as usize] = '#';
-                current += diff;
+    /// How a particle moves. It takes the first free move in order, and comes to rest when there is
+    /// none. It never moves back onto its own path, so sideways moves can't go back and forth.
+    #[derive(Clone)]
+    pub struct Particle {
+        pub tile: char,
+        pub moves: Vec<Pos>,
+    }
+
+    impl Particle {
+        /// Falls down, then diagonally left, then diagonally right.
+        pub fn sand() -> Particle {
+            Particle {
+                tile: 'o',
+                moves: vec![Pos::new(1, 0), Pos::new(1, -1), Pos::new(1, 1)],
+            }
+        }
+
+        /// Falls down, otherwise spreads left, then right.
+        pub fn water() -> Particle {
+            Particle {
+                tile: '~',
+                moves: vec![Pos::new(1, 0), Pos::new(0, -1), Pos::new(0, 1)],
             }
-            grid[current.row as usize][current.col as usize] = '#';
         }
     }
 
-    grid
-}
+    pub struct Source {
+        pub pos: Pos,
+        pub particle: Particle,
+    }
+
+    /// The cave, stored sparsely so it can extend as far as the particles go.
+    /// '#'=rock, particles use their own tile, anything not stored is empty.
+    pub struct Cave {
+        tiles: HashMap<Pos, char>,
+        floors: Vec<Floor>,
+        /// Below this particles can only fall into the abyss
+        lowest: i32,
+    }
+
+    impl Cave {
+        /// Build the cave from the rock seams
+        pub fn new(seams: &[Vec<Pos>], floors: Vec<Floor>) -> Cave {
+            let mut tiles = HashMap::new();
+            let mut lowest = floors.iter().map(|floor| floor.row).max().unwrap_or(0);
+
+            // Add the seams
+            for seam in seams {
+                for points in seam.windows(2) {
+                    let from = &points[0];
+                    let to = &points[1];
+
+                    let diff = Pos::new(
+                        (to.row - from.row).clamp(-1, 1),
+                        (to.col - from.col).clamp(-1, 1),
+                    );
+
+                    let mut current = *from;
+                    while current != *to {
+                        tiles.insert(current, '#');
+                        current += diff;
+                    }
+                    tiles.insert(current, '#');
+                    lowest = lowest.max(from.row).max(to.row);
+                }
+            }
+
+            Cave {
+                tiles,
+                floors,
+                lowest,
+            }
+        }
+
+        pub fn get(&self, pos: Pos) -> char {
+            if self.floors.iter().any(|floor| floor.contains(pos)) {
+                return '#';
+            }
 
-/// Print the grid to console
-#[allow(dead_code)]
-fn print_grid(grid: &[Vec<char>]) {
-    for row in grid {
-        for col in &row[450..505] {
-            print!("{}", col);
+            *self.tiles.get(&pos).unwrap_or(&'.')
+        }
+
+        /// The top left and bottom right corners of everything in the cave
+        pub fn bounds(&self) -> (Pos, Pos) {
+            let mut min = Pos::new(i32::MAX, i32::MAX);
+            let mut max = Pos::new(i32::MIN, i32::MIN);
+            for pos in self.tiles.keys() {
+                min = Pos::new(min.row.min(pos.row), min.col.min(pos.col));
+                max = Pos::new(max.row.max(pos.row), max.col.max(pos.col));
+            }
+
+            for floor in &self.floors {
+                max.row = max.row.max(floor.row);
+                if let Some((from, to)) = floor.cols {
+                    min.col = min.col.min(from);
+                    max.col = max.col.max(to);
+                }
+            }
+
+            (min, max)
         }
-        println!();
     }
-}
 
-fn run_till_abyss(grid: &mut [Vec<char>]) -> usize {
-    let mut count = 0;
-
-    loop {
-        let mut sand_pos = SOURCE_POS;
-
-        loop {
-            let down = sand_pos + Pos::new(1, 0);
-            let left_down = sand_pos + Pos::new(1, -1);
-            let right_down = sand_pos + Pos::new(1, 1);
-
-            let mut moved = false;
-            for mv in [down, left_down, right_down] {
-                // OOB
-                if mv.row < 0
-                    || mv.row >= grid.len() as i32
-                    || mv.col < 0
-                    || mv.col >= grid[0].len() as i32
-                {
-                    continue;
+    /// Where a source's particles have fallen so far.
+    struct Flow {
+        path: Vec<Pos>,
+        on_path: HashSet<Pos>,
+        settled: usize,
+    }
+
+    impl Flow {
+        /// A particle came to rest at pos. The path only stays valid up to the first position
+        /// that could have moved to pos, as that is where the next particle may now go elsewhere.
+        fn blocked(&mut self, pos: Pos, particle: &Particle) {
+            let changed = self.path.iter().position(|step| {
+                *step == pos || particle.moves.iter().any(|mv| *step + *mv == pos)
+            });
+
+            if let Some(idx) = changed {
+                let keep = if self.path[idx] == pos { idx } else { idx + 1 };
+                for step in self.path.drain(keep..) {
+                    self.on_path.remove(&step);
                 }
+            }
+        }
+    }
+
+    /// Pours particles from every source in turn until one is lost or every source is blocked.
+    /// A particle is lost once it falls below everything, or flows sideways further than a pile
+    /// of sand could spread from any rock or source, as only an endless floor could be there.
+    /// Each particle resumes from the last free position on the previous particle's path rather
+    /// than falling from the source again.
+    /// on_settle sees the cave and the path of the particle each time one comes to rest.
+    /// Returns the number of particles that came to rest from each source and the final cave.
+    pub fn pour(
+        mut cave: Cave,
+        sources: &[Source],
+        mut on_settle: impl FnMut(&Cave, &[Pos]),
+    ) -> (Vec<usize>, Cave) {
+        let mut flows = sources
+            .iter()
+            .map(|source| Flow {
+                path: vec![source.pos],
+                on_path: HashSet::from([source.pos]),
+                settled: 0,
+            })
+            .collect::<Vec<_>>();
+
+        let (min, max) = cave.bounds();
+        let spread = cave.lowest + 1;
+        let min_col = sources
+            .iter()
+            .map(|source| source.pos.col)
+            .fold(min.col, i32::min)
+            - spread;
+        let max_col = sources
+            .iter()
+            .map(|source| source.pos.col)
+            .fold(max.col, i32::max)
+            + spread;
 
-                // Rock or sand
-                if grid[mv.row as usize][mv.col as usize] != '.' {
+        'pouring: loop {
+            let mut any_active = false;
+
+            for source_idx in 0..sources.len() {
+                let particle = &sources[source_idx].particle;
+                let flow = &mut flows[source_idx];
+                if flow.path.is_empty() {
                     continue;
                 }
+                any_active = true;
+
+                // Fall until this particle comes to rest
+                let rest = loop {
+                    let pos = *flow.path.last().unwrap();
+                    let next = particle
+                        .moves
+                        .iter()
+                        .map(|mv| pos + *mv)
+                        .find(|mv| cave.get(*mv) == '.' && !flow.on_path.contains(mv));
 
-                sand_pos = mv;
-                moved = true;
+                    match next {
+                        Some(next) => {
+                            if next.row > cave.lowest || next.col < min_col || next.col > max_col {
+                                break 'pouring;
+                            }
+
+                            flow.path.push(next);
+                            flow.on_path.insert(next);
+                        }
+                        None => break pos,
+                    }
+                };
+
+                cave.tiles.insert(rest, particle.tile);
+                flow.settled += 1;
+                on_settle(&cave, &flow.path);
+                for (flow, source) in flows.iter_mut().zip(sources) {
+                    flow.blocked(rest, &source.particle);
+                }
+            }
+
+            if !any_active {
                 break;
             }
+        }
+
+        (flows.iter().map(|flow| flow.settled).collect(), cave)
+    }
+}
+
+/// Where rendered frames go.
+enum Target {
+    /// Redraw the terminal in colour
+    Terminal,
+    /// Numbered PPM images in a directory
+    Images(String),
+}
 
-            if sand_pos.row + 1 == grid.len() as i32 {
-                return count;
+/// Draws the cave every so many particles, cropped to the rocks, the particles and the path of
+/// the particle that has just fallen.
+struct Renderer {
+    every: usize,
+    target: Target,
+    scale: usize,
+    frame: usize,
+    particles: usize,
+}
+
+impl Renderer {
+    fn colour(tile: char) -> (u8, u8, u8) {
+        match tile {
+            '#' => (110, 110, 120),
+            'o' => (230, 190, 90),
+            '~' => (60, 120, 230),
+            '*' => (230, 60, 40),
+            _ => (20, 20, 25),
+        }
+    }
+
+    fn settled(&mut self, cave: &Cave, falling: &[Pos]) {
+        self.particles += 1;
+        if self.particles.is_multiple_of(self.every) {
+            self.draw(cave, falling);
+        }
+    }
+
+    fn draw(&mut self, cave: &Cave, falling: &[Pos]) {
+        let (mut min, mut max) = cave.bounds();
+        for pos in falling {
+            min = Pos::new(min.row.min(pos.row), min.col.min(pos.col));
+            max = Pos::new(max.row.max(pos.row), max.col.max(pos.col));
+        }
+
+        // The path of the falling particle, except where it came to rest
+        let tile = |pos: Pos| {
+            if falling[..falling.len() - 1].contains(&pos) {
+                '*'
+            } else {
+                cave.get(pos)
             }
+        };
 
-            if sand_pos == SOURCE_POS {
-                return count;
+        match &self.target {
+            Target::Terminal => {
+                let mut out = String::from("\x1b[2J\x1b[H");
+                for row in min.row..=max.row {
+                    for col in min.col..=max.col {
+                        let tile = tile(Pos::new(row, col));
+                        let (r, g, b) = Renderer::colour(tile);
+                        out += &format!("\x1b[38;2;{};{};{}m{}", r, g, b, tile);
+                    }
+                    out += "\x1b[0m\n";
+                }
+                println!("{}Particles {}", out, self.particles);
             }
+            Target::Images(dir) => {
+                let width = (max.col - min.col + 1) as usize * self.scale;
+                let height = (max.row - min.row + 1) as usize * self.scale;
+                let mut image = format!("P6\n{} {}\n255\n", width, height).into_bytes();
 
-            if !moved {
-                grid[sand_pos.row as usize][sand_pos.col as usize] = 'o';
-                count += 1;
-                break;
+                for row in min.row..=max.row {
+                    let mut line = vec![];
+                    for col in min.col..=max.col {
+                        let (r, g, b) = Renderer::colour(tile(Pos::new(row, col)));
+                        for _ in 0..self.scale {
+                            line.extend([r, g, b]);
+                        }
+                    }
+                    for _ in 0..self.scale {
+                        image.extend(&line);
+                    }
+                }
+
+                let path = format!("{}/frame_{:05}.ppm", dir, self.frame);
+                std::fs::write(path, image).unwrap();
             }
         }
+
+        self.frame += 1;
     }
 }
 
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|idx| args.get(idx + 1))
+        .cloned()
+}
+
+/// Options, each can be given more than once:
+/// --sand COL,ROW          add a source of sand
+/// --water COL,ROW         add a source of water
+/// --floor ROW[,FROM,TO]   add a floor, limited to columns FROM to TO if given
+/// Rendering the part A cave, or the cave with the sources given above:
+/// --render terminal|DIR   draw frames to the terminal, or save them as images in DIR
+/// --every N               draw a frame every N particles, defaults to 1
+/// --scale N               pixel size of saved images, defaults to 4
 fn main() {
     let input = include_str!("../../assets/day14.txt");
     let (seams, max_pos) = load_seams(input);
+    let args = std::env::args().collect::<Vec<_>>();
 
-    let mut a_grid = build_grid(&seams, &max_pos);
-    let units_till_abyss = run_till_abyss(&mut a_grid);
-    println!("Units of sand till abyss {}", units_till_abyss);
+    let sand = [Source {
+        pos: SOURCE_POS,
+        particle: Particle::sand(),
+    }];
+
+    let mut renderer = arg_value(&args, "--render").map(|target| {
+        let target = match target.as_str() {
+            "terminal" => Target::Terminal,
+            dir => {
+                std::fs::create_dir_all(dir).unwrap();
+                Target::Images(dir.to_string())
+            }
+        };
+
+        Renderer {
+            every: arg_value(&args, "--every").map_or(1, |val| val.parse::<usize>().unwrap()),
+            target,
+            scale: arg_value(&args, "--scale").map_or(4, |val| val.parse::<usize>().unwrap()),
+            frame: 0,
+            particles: 0,
+        }
+    });
+    let mut on_settle = |cave: &Cave, falling: &[Pos]| {
+        if let Some(renderer) = &mut renderer {
+            renderer.settled(cave, falling);
+        }
+    };
 
-    let mut b_grid = build_grid(&seams, &max_pos);
-    for c in b_grid.last_mut().unwrap() {
-        *c = '#';
+    let custom = args.iter().any(|arg| arg == "--sand" || arg == "--water");
+    let (units_till_abyss, _) = if custom {
+        cave::pour(Cave::new(&seams, vec![]), &sand, |_, _| {})
+    } else {
+        cave::pour(Cave::new(&seams, vec![]), &sand, &mut on_settle)
+    };
+    println!("Units of sand till abyss {}", units_till_abyss[0]);
+
+    // The floor is 2 below the lowest rock
+    let floor = Floor {
+        row: max_pos.row + 2,
+        cols: None,
+    };
+    let (units_till_blocked, _) = cave::pour(Cave::new(&seams, vec![floor]), &sand, |_, _| {});
+    println!("Units of sand till blocked {}", units_till_blocked[0]);
+
+    let mut sources = vec![];
+    let mut floors = vec![];
+    for opt in args.windows(2) {
+        let vals = || {
+            opt[1]
+                .split(',')
+                .map(|val| val.parse::<i32>().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        match opt[0].as_str() {
+            "--sand" => sources.push(Source {
+                pos: Pos::new(vals()[1], vals()[0]),
+                particle: Particle::sand(),
+            }),
+            "--water" => sources.push(Source {
+                pos: Pos::new(vals()[1], vals()[0]),
+                particle: Particle::water(),
+            }),
+            "--floor" => {
+                let vals = vals();
+                floors.push(Floor {
+                    row: vals[0],
+                    cols: vals.get(1..3).map(|cols| (cols[0], cols[1])),
+                })
+            }
+            _ => {}
+        }
     }
 
-    let units_till_blocked = run_till_abyss(&mut b_grid);
-    println!("Units of sand till blocked {}", units_till_blocked + 1);
+    if !sources.is_empty() {
+        let (settled, _) = cave::pour(Cave::new(&seams, floors), &sources, on_settle);
+        for (source, count) in sources.iter().zip(settled) {
+            println!(
+                "Source at {},{} settled {}",
+                source.pos.col, source.pos.row, count
+            );
+        }
+    }
 }
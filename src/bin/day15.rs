@@ -12,7 +12,6 @@ use regex::Regex;
 /// A beacon is not detected by any sensor. It must have coords >= 0, <= 4000000.
 /// Find its tuning frequency by multiplying its x coord by 4000000 and then adding its y coord.
 /// There is only one possible position for the beacon.
-
 type Pos = (i64, i64);
 
 /// The grid of all sensors and beacons
@@ -41,30 +40,104 @@ impl Grid {
         self.areas.iter().any(|area| area.in_area(pos))
     }
 
-    fn edges(&self) -> Vec<Pos> {
-        self.areas
+    /// The ranges of x covered by sensors on row y, merged and sorted.
+    fn row_coverage(&self, y: i64) -> Vec<(i64, i64)> {
+        let mut ranges = self
+            .areas
             .iter()
-            .flat_map(|area| area.edges())
-            .collect::<Vec<_>>()
-    }
-
-    #[allow(dead_code)]
-    fn print(&self) {
-        println!("min: {:?}, max: {:?}", self.min, self.max);
-
-        for y in self.min.1..self.max.1 {
-            for x in self.min.0..self.max.0 {
-                if self.has_beacon(&(x, y)) {
-                    print!("b");
-                } else if self.has_sensor(&(x, y)) {
-                    print!("s");
-                } else {
-                    print!(".")
+            .filter_map(|area| area.row_range(y))
+            .collect::<Vec<_>>();
+        ranges.sort_unstable();
+
+        let mut merged: Vec<(i64, i64)> = Vec::new();
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.0 <= last.1 + 1 => last.1 = last.1.max(range.1),
+                _ => merged.push(range),
+            }
+        }
+
+        merged
+    }
+
+    /// A PPM image of the window from min to max, width pixels across, each pixel showing the
+    /// grid at its center. Sensor areas are shaded in their own colour, sensors are white,
+    /// beacons yellow and the gap, if given, red.
+    fn render(&self, min: Pos, max: Pos, width: usize, gap: Option<Pos>) -> Vec<u8> {
+        let span = ((max.0 - min.0 + 1) as f64, (max.1 - min.1 + 1) as f64);
+        let height = ((width as f64 * span.1 / span.0).round() as usize).max(1);
+        let scale = span.0 / width as f64;
+
+        let to_pixel = |pos: &Pos| {
+            (
+                ((pos.0 - min.0) as f64 / scale) as i64,
+                ((pos.1 - min.1) as f64 / scale) as i64,
+            )
+        };
+
+        let palette = [
+            (90, 60, 160),
+            (40, 110, 170),
+            (30, 140, 110),
+            (120, 140, 40),
+            (160, 90, 40),
+            (150, 50, 90),
+        ];
+
+        let mut pixels = vec![(10, 10, 15); width * height];
+        for (py, row) in pixels.chunks_mut(width).enumerate() {
+            for (px, pixel) in row.iter_mut().enumerate() {
+                let pos = (
+                    min.0 + ((px as f64 + 0.5) * scale) as i64,
+                    min.1 + ((py as f64 + 0.5) * scale) as i64,
+                );
+
+                let covering = self
+                    .areas
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, area)| area.in_area(&pos))
+                    .map(|(idx, _)| palette[idx % palette.len()])
+                    .collect::<Vec<_>>();
+
+                if !covering.is_empty() {
+                    let count = covering.len() as u32;
+                    let sum = covering.iter().fold((0, 0, 0), |sum, colour| {
+                        (sum.0 + colour.0, sum.1 + colour.1, sum.2 + colour.2)
+                    });
+                    *pixel = (sum.0 / count, sum.1 / count, sum.2 / count);
                 }
             }
+        }
+
+        // Markers are drawn over a few pixels so they stay visible when zoomed out
+        let mut mark = |pos: &Pos, radius: i64, colour: (u32, u32, u32)| {
+            let center = to_pixel(pos);
+            for y in center.1 - radius..=center.1 + radius {
+                for x in center.0 - radius..=center.0 + radius {
+                    if x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height {
+                        pixels[y as usize * width + x as usize] = colour;
+                    }
+                }
+            }
+        };
+
+        for sensor in &self.sensors {
+            mark(sensor, 1, (240, 240, 240));
+        }
+        for beacon in &self.beacons {
+            mark(beacon, 1, (250, 210, 40));
+        }
+        if let Some(gap) = gap {
+            mark(&gap, 3, (240, 40, 30));
+        }
 
-            println!();
+        let mut image = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+        for (r, g, b) in pixels {
+            image.extend([r as u8, g as u8, b as u8]);
         }
+
+        image
     }
 
     fn from(input: &str) -> Self {
@@ -99,8 +172,8 @@ impl Grid {
             min.0 = min.0.min(area.left());
             min.1 = min.1.min(area.top());
 
-            max.0 = max.0.max(area.bottom());
-            max.1 = max.1.max(area.right());
+            max.0 = max.0.max(area.right());
+            max.1 = max.1.max(area.bottom());
 
             areas.push(area);
         }
@@ -153,21 +226,24 @@ impl Area {
         self.range >= dist
     }
 
-    /// Get the edges of the area as individual grid pos
-    fn edges(&self) -> Vec<Pos> {
-        let mut edges = Vec::new();
-        let edge_dist = self.range + 1;
+    /// The diagonal lines just outside the area, as the x + y of the two running one way and the
+    /// x - y of the two running the other.
+    fn boundary_lines(&self) -> ([i64; 2], [i64; 2]) {
+        let sum = self.pos.0 + self.pos.1;
+        let diff = self.pos.0 - self.pos.1;
+        let dist = self.range + 1;
 
-        for x_step in 0..edge_dist {
-            let y_step = edge_dist - x_step;
+        ([sum - dist, sum + dist], [diff - dist, diff + dist])
+    }
 
-            edges.push((self.pos.0 + x_step, self.pos.1 + y_step));
-            edges.push((self.pos.1 + y_step, self.pos.0 - x_step));
-            edges.push((self.pos.0 - x_step, self.pos.1 - y_step));
-            edges.push((self.pos.1 - y_step, self.pos.0 + x_step));
+    /// The x range covered on row y, if the area reaches it.
+    fn row_range(&self, y: i64) -> Option<(i64, i64)> {
+        let half_width = self.range - (y - self.pos.1).abs();
+        if half_width < 0 {
+            return None;
         }
 
-        edges
+        Some((self.pos.0 - half_width, self.pos.0 + half_width))
     }
 }
 
@@ -176,46 +252,308 @@ fn m_dist(a: &Pos, b: &Pos) -> i64 {
     (a.0 - b.0).abs() + (a.1 - b.1).abs()
 }
 
-fn count_known_empty(grid: &mut Grid, y: i64) -> i64 {
-    let mut count = 0;
+/// The tuning frequency is x * TUNING_MULT + y.
+const TUNING_MULT: i64 = 4000000;
+
+fn count_known_empty(grid: &Grid, y: i64) -> i64 {
+    let coverage = grid.row_coverage(y);
+    let covered = coverage
+        .iter()
+        .map(|range| range.1 - range.0 + 1)
+        .sum::<i64>();
+
+    // Sensors and beacons on the row aren't empty
+    let items = grid
+        .sensors
+        .union(&grid.beacons)
+        .filter(|pos| {
+            pos.1 == y
+                && coverage
+                    .iter()
+                    .any(|range| pos.0 >= range.0 && pos.0 <= range.1)
+        })
+        .count() as i64;
+
+    covered - items
+}
 
-    for x in grid.min.0..grid.max.0 {
-        let pos = (x, y);
-        if !grid.has_item(&pos) && grid.in_area(&pos) {
-            count += 1;
-        }
+/// The first cell in the box from min to max that no sensor covers. A lone cell has covered
+/// cells all round it, so it lies just outside the areas, where their boundary lines cross or
+/// where one meets the edge of the box. It can also sit between two parallel lines, a step
+/// along from where a line across them crosses, so the cells round each crossing are tried too.
+fn find_hidden_freq(grid: &Grid, min: Pos, max: Pos) -> Option<(Pos, i64)> {
+    let mut sums = HashSet::new();
+    let mut diffs = HashSet::new();
+    for area in &grid.areas {
+        let (area_sums, area_diffs) = area.boundary_lines();
+        sums.extend(area_sums);
+        diffs.extend(area_diffs);
     }
 
-    count
+    let crossings = sums.iter().flat_map(|sum| {
+        diffs.iter().flat_map(move |diff| {
+            (-1..=1).flat_map(move |du| {
+                (-1..=1)
+                    .map(move |dv| (sum + du, diff + dv))
+                    .filter(|(sum, diff)| (sum - diff) % 2 == 0)
+                    .map(|(sum, diff)| ((sum + diff) / 2, (sum - diff) / 2))
+            })
+        })
+    });
+    let edges = sums
+        .iter()
+        .flat_map(|sum| {
+            [
+                (min.0, sum - min.0),
+                (max.0, sum - max.0),
+                (sum - min.1, min.1),
+                (sum - max.1, max.1),
+            ]
+        })
+        .chain(diffs.iter().flat_map(|diff| {
+            [
+                (min.0, min.0 - diff),
+                (max.0, max.0 - diff),
+                (diff + min.1, min.1),
+                (diff + max.1, max.1),
+            ]
+        }));
+    let corners = [min, (max.0, min.1), (min.0, max.1), max];
+
+    crossings
+        .chain(edges)
+        .chain(corners)
+        .filter(|pos| pos.0 >= min.0 && pos.1 >= min.1 && pos.0 <= max.0 && pos.1 <= max.1)
+        .find(|pos| !grid.has_item(pos) && !grid.in_area(pos))
+        .map(|pos| (pos, pos.0 * TUNING_MULT + pos.1))
+}
+
+/// A rectangle in diamond space, where u = x + y and v = x - y, so its sides run diagonally
+/// across the grid like the edges of the sensor areas. Bounds are inclusive.
+#[derive(Debug, Clone, Copy)]
+struct DiamondRect {
+    u: (i64, i64),
+    v: (i64, i64),
 }
 
-fn find_hidden_freq(grid: &mut Grid, min: Pos, max: Pos) -> Option<(Pos, i64)> {
-    for pos in grid.edges() {
-        if pos.0 < min.0 || pos.1 < min.1 || pos.0 > max.0 || pos.1 > max.1 {
-            continue;
+impl DiamondRect {
+    fn of_area(area: &Area) -> Self {
+        let u = area.pos.0 + area.pos.1;
+        let v = area.pos.0 - area.pos.1;
+
+        DiamondRect {
+            u: (u - area.range, u + area.range),
+            v: (v - area.range, v + area.range),
+        }
+    }
+
+    /// The smallest diamond rect holding the box from min to max.
+    fn around(min: Pos, max: Pos) -> Self {
+        DiamondRect {
+            u: (min.0 + min.1, max.0 + max.1),
+            v: (min.0 - max.1, max.0 - min.1),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.u.0 > self.u.1 || self.v.0 > self.v.1
+    }
+
+    /// The x and y ranges the rect spans on the grid.
+    fn grid_bounds(&self) -> ((i64, i64), (i64, i64)) {
+        let x = (
+            (self.u.0 + self.v.0).div_euclid(2),
+            (self.u.1 + self.v.1).div_euclid(2),
+        );
+        let y = (
+            (self.u.0 - self.v.1).div_euclid(2),
+            (self.u.1 - self.v.0).div_euclid(2),
+        );
+
+        (x, y)
+    }
+
+    /// The rect with other cut out of it, as up to 4 rects.
+    fn subtract(&self, other: &DiamondRect) -> Vec<DiamondRect> {
+        let overlap = DiamondRect {
+            u: (self.u.0.max(other.u.0), self.u.1.min(other.u.1)),
+            v: (self.v.0.max(other.v.0), self.v.1.min(other.v.1)),
+        };
+        if overlap.is_empty() {
+            return vec![*self];
         }
 
-        if grid.has_item(&pos) || grid.in_area(&pos) {
-            continue;
+        [
+            DiamondRect {
+                u: (self.u.0, overlap.u.0 - 1),
+                v: self.v,
+            },
+            DiamondRect {
+                u: (overlap.u.1 + 1, self.u.1),
+                v: self.v,
+            },
+            DiamondRect {
+                u: overlap.u,
+                v: (self.v.0, overlap.v.0 - 1),
+            },
+            DiamondRect {
+                u: overlap.u,
+                v: (overlap.v.1 + 1, self.v.1),
+            },
+        ]
+        .into_iter()
+        .filter(|rect| !rect.is_empty())
+        .collect()
+    }
+
+    /// The number of grid cells in the rect that are also within the box from min to max. Each
+    /// column runs from the highest of the lines below it to the lowest of those above it, so
+    /// between the places where lines cross the column height changes steadily with x and each
+    /// stretch of columns adds up as an arithmetic series.
+    fn area_within(&self, min: Pos, max: Pos) -> i64 {
+        let (x_range, _) = self.grid_bounds();
+        let (first, last) = (x_range.0.max(min.0), x_range.1.min(max.0));
+
+        // Lines as y = c + m * x
+        let lows = [(min.1, 0), (self.u.0, -1), (-self.v.1, 1)];
+        let highs = [(max.1, 0), (self.u.1, -1), (-self.v.0, 1)];
+        let at = |line: &(i64, i64), x: i64| line.0 + line.1 * x;
+
+        let mut starts = vec![first, last + 1];
+        for lines in [&lows, &highs] {
+            for (idx, a) in lines.iter().enumerate() {
+                for b in &lines[idx + 1..] {
+                    // The first column past where they cross
+                    let (num, den) = match a.1 - b.1 {
+                        0 => continue,
+                        den if den < 0 => (a.0 - b.0, -den),
+                        den => (b.0 - a.0, den),
+                    };
+                    starts.push(num.div_euclid(den) + 1);
+                }
+            }
+        }
+        starts.retain(|x| (first..=last + 1).contains(x));
+        starts.sort_unstable();
+        starts.dedup();
+
+        let mut area = 0;
+        for stretch in starts.windows(2) {
+            let (start, end) = (stretch[0], stretch[1] - 1);
+            let low = lows
+                .iter()
+                .max_by_key(|line| (at(line, start), at(line, end)))
+                .unwrap();
+            let high = highs
+                .iter()
+                .min_by_key(|line| (at(line, start), at(line, end)))
+                .unwrap();
+
+            // The height is c + m * x, only count the columns it is at least 1
+            let (c, m) = (high.0 - low.0 + 1, high.1 - low.1);
+            let (start, end) = match m {
+                0 if c < 1 => continue,
+                0 => (start, end),
+                m if m > 0 => (start.max(-(c - 1).div_euclid(m)), end),
+                m => (start, end.min((c - 1).div_euclid(-m))),
+            };
+            if start <= end {
+                area += (c + m * start + c + m * end) * (end - start + 1) / 2;
+            }
         }
 
-        return Some((pos, (pos.0 * 4000000) + pos.1));
+        area
+    }
+}
+
+/// Every region of the box from min to max that no sensor covers, with the number of cells in
+/// each. The regions don't overlap.
+fn uncovered_regions(grid: &Grid, min: Pos, max: Pos) -> Vec<(DiamondRect, i64)> {
+    let mut regions = vec![DiamondRect::around(min, max)];
+
+    for area in &grid.areas {
+        let covered = DiamondRect::of_area(area);
+        regions = regions
+            .iter()
+            .flat_map(|region| region.subtract(&covered))
+            .filter(|region| {
+                // Drop anything that has left the box
+                let (x, y) = region.grid_bounds();
+                x.0 <= max.0 && x.1 >= min.0 && y.0 <= max.1 && y.1 >= min.1
+            })
+            .collect();
     }
 
-    None
+    regions
+        .into_iter()
+        .map(|region| (region, region.area_within(min, max)))
+        .filter(|(_, area)| *area > 0)
+        .collect()
 }
 
+fn arg_value(args: &[String], name: &str) -> Option<i64> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|idx| args.get(idx + 1))
+        .map(|val| val.parse::<i64>().unwrap())
+}
+
+/// Options:
+/// --row N             the row to count known empty positions on, defaults to 2000000
+/// --max N             the hidden beacon has coords from 0 to N, defaults to 4000000
+/// --image PATH        save a PPM image of the sensors, defaults to showing all of them
+/// --width N           width of the image in pixels, defaults to 800
+/// --zoom X,Y,RADIUS   show only the window of RADIUS around X,Y
 fn main() {
     let input = include_str!("../../assets/day15.txt");
-    let mut grid = Grid::from(input);
+    let grid = Grid::from(input);
+    let args = std::env::args().collect::<Vec<_>>();
+    let row = arg_value(&args, "--row").unwrap_or(2000000);
+    let max = arg_value(&args, "--max").unwrap_or(4000000);
 
-    let known_empty = count_known_empty(&mut grid, 2000000);
+    let known_empty = count_known_empty(&grid, row);
     println!("Known empty {}", known_empty);
 
-    let (hidden_pos, hidden_freq) =
-        find_hidden_freq(&mut grid, (0, 0), (4000000, 4000000)).unwrap_or(((0, 0), -1));
+    let hidden = find_hidden_freq(&grid, (0, 0), (max, max));
+    match hidden {
+        Some((pos, freq)) => println!("Hidden freq {} at {},{}", freq, pos.0, pos.1),
+        None => println!("No hidden beacon, every position is covered"),
+    }
+
+    let regions = uncovered_regions(&grid, (0, 0), (max, max));
+    for (region, area) in &regions {
+        let (x, y) = region.grid_bounds();
+        println!(
+            "Uncovered u={}..{} v={}..{} (x={}..{} y={}..{}) area {}",
+            region.u.0, region.u.1, region.v.0, region.v.1, x.0, x.1, y.0, y.1, area
+        );
+    }
+    let total = regions.iter().map(|(_, area)| area).sum::<i64>();
     println!(
-        "Hidden freq {} at {},{}",
-        hidden_freq, hidden_pos.0, hidden_pos.1
+        "{} uncovered regions, {} cells, beacon position unique: {}",
+        regions.len(),
+        total,
+        total == 1
     );
+
+    if let Some(idx) = args.iter().position(|arg| arg == "--image") {
+        let (min, max) = match args.iter().position(|arg| arg == "--zoom") {
+            Some(zoom_idx) => {
+                let zoom = args[zoom_idx + 1]
+                    .split(',')
+                    .map(|val| val.parse::<i64>().unwrap())
+                    .collect::<Vec<_>>();
+                (
+                    (zoom[0] - zoom[2], zoom[1] - zoom[2]),
+                    (zoom[0] + zoom[2], zoom[1] + zoom[2]),
+                )
+            }
+            None => (grid.min, grid.max),
+        };
+
+        let width = arg_value(&args, "--width").unwrap_or(800) as usize;
+        let gap = hidden.map(|(pos, _)| pos);
+        std::fs::write(&args[idx + 1], grid.render(min, max, width, gap)).unwrap();
+    }
 }
+
@@ -1,9 +1,10 @@
 use rayon::prelude::*;
 use regex::Regex;
-use std::{
-    collections::{BTreeSet, HashMap},
-    time,
-};
+use std::{collections::HashMap, time};
+
+/// The tables for sharing out valves between agents have an entry for every set of valves, and
+/// each extra agent takes 3^n steps to pair up, so this is about as far as they go.
+const MAX_SHARED: usize = 20;
 
 /// The input describes a network of pipes and pressure release valves in an angry volcano.
 /// The valves are in different rooms.
@@ -15,70 +16,25 @@ use std::{
 /// What is the most pressure that can be released?
 /// Part B:
 /// What if an elephant helps? It takes 4 minutes to teach them what to do.
-
-#[derive(Clone)]
-struct Valve {
-    label: String,
-    flow_rate: i32,
-    tunnels: Vec<String>,
-    costs: HashMap<String, i32>,
-}
-
-type Valves = HashMap<String, Valve>;
-
-#[derive(Debug, Clone)]
-struct State {
-    turned_on: BTreeSet<String>,
-    total: i32,
-    plumbers: Vec<Plumber>,
-}
-
-#[derive(Debug, Clone)]
-struct Plumber {
-    current_room: String,
-    previous_room: String,
-    remaining: i32,
-    path: String,
-}
-
-impl State {
-    fn get_moves(&self, valves: &Valves, plumber_idx: usize) -> Vec<State> {
-        let mut moves = vec![];
-        let valve = &valves[&self.plumbers[plumber_idx].current_room];
-
-        // Each move moves to a dest and turns on the valve.
-        // We always turn the valve on as out dests cover paths to all valves.
-        for (dest, cost) in &valve.costs {
-            if !self.turned_on.contains(dest)
-                && self.plumbers[plumber_idx].remaining > *cost
-                && valves[dest].flow_rate > 0
-            {
-                let mut new_state = self.clone();
-                new_state.turned_on.insert(dest.clone());
-                new_state.plumbers[plumber_idx].remaining -= cost + 1;
-                new_state.total +=
-                    new_state.plumbers[plumber_idx].remaining * valves[dest].flow_rate;
-                new_state.plumbers[plumber_idx].previous_room =
-                    new_state.plumbers[plumber_idx].current_room.clone();
-                new_state.plumbers[plumber_idx].current_room = dest.clone();
-                new_state.plumbers[plumber_idx].path =
-                    format!("{} -> {}", new_state.plumbers[plumber_idx].path, dest);
-
-                moves.push(new_state);
-            }
-        }
-
-        moves
-    }
+struct Network {
+    labels: Vec<String>,
+    flow_rates: Vec<i32>,
+    tunnels: Vec<Vec<usize>>,
+    /// Minutes to move between any two valves
+    costs: Vec<Vec<i32>>,
+    /// Minutes to open each valve
+    open_costs: Vec<i32>,
+    /// The valves worth opening. Bit i of an open mask is set when useful[i] is open.
+    useful: Vec<usize>,
 }
 
-impl Valve {
-    fn from(input: &str) -> Valves {
+impl Network {
+    fn from(input: &str) -> Network {
         let reg = Regex::new(r"^Valve ([[:upper:]]{2}) has flow rate=(\d*); tunnel[s]? lead[s]? to valve[s]? ((?:[[:upper:]]{2}(?:, *)?)*)$").unwrap();
 
         // Parse the input
-        let mut valves = input
-            .par_lines()
+        let parsed = input
+            .lines()
             .map(|line| {
                 let caps = reg.captures(line).unwrap();
                 let tunnels = caps[3]
@@ -86,34 +42,49 @@ impl Valve {
                     .map(|l| l.trim().to_string())
                     .collect::<Vec<_>>();
 
-                let valve = Valve {
-                    label: String::from(&caps[1]),
-                    flow_rate: caps[2].parse::<i32>().unwrap(),
+                (
+                    caps[1].to_string(),
+                    caps[2].parse::<i32>().unwrap(),
                     tunnels,
-                    costs: HashMap::new(),
-                };
-
-                (valve.label.clone(), valve)
+                )
             })
-            .collect::<HashMap<String, Valve>>();
+            .collect::<Vec<_>>();
+
+        // Intern the labels
+        let labels = parsed
+            .iter()
+            .map(|(label, _, _)| label.clone())
+            .collect::<Vec<_>>();
+        let index = labels
+            .iter()
+            .enumerate()
+            .map(|(idx, label)| (label.clone(), idx))
+            .collect::<HashMap<_, _>>();
+
+        let flow_rates = parsed.iter().map(|(_, rate, _)| *rate).collect::<Vec<_>>();
+        let tunnels = parsed
+            .iter()
+            .map(|(_, _, tunnels)| tunnels.iter().map(|label| index[label]).collect())
+            .collect::<Vec<Vec<usize>>>();
 
         // Work out the cost for moving between any two valves
-        let total_valves = valves.len();
-        let valve_costs = valves
-            .par_iter()
-            .map(|(label, valve)| {
-                let mut costs = HashMap::new();
-                let mut cost = 1;
-
-                let mut layer = valve.tunnels.clone();
-                while costs.len() < total_valves - 1 {
-                    let mut next_layer: Vec<String> = vec![];
-
-                    for dest in &layer {
-                        if !costs.contains_key(dest) && *dest != valve.label {
-                            costs.insert(dest.clone(), cost);
+        let costs = (0..labels.len())
+            .into_par_iter()
+            .map(|from| {
+                let mut costs = vec![i32::MAX; labels.len()];
+                costs[from] = 0;
 
-                            next_layer.extend(valves[dest].tunnels.clone());
+                let mut layer = vec![from];
+                let mut cost = 1;
+                while !layer.is_empty() {
+                    let mut next_layer = vec![];
+
+                    for valve in layer {
+                        for dest in &tunnels[valve] {
+                            if costs[*dest] == i32::MAX {
+                                costs[*dest] = cost;
+                                next_layer.push(*dest);
+                            }
                         }
                     }
 
@@ -121,146 +92,777 @@ impl Valve {
                     cost += 1;
                 }
 
-                (label.clone(), costs)
+                costs
             })
-            .collect::<HashMap<String, HashMap<String, i32>>>();
+            .collect::<Vec<_>>();
+
+        let useful = (0..labels.len())
+            .filter(|valve| flow_rates[*valve] > 0)
+            .collect::<Vec<_>>();
+        assert!(useful.len() <= 64, "More than 64 valves with flow");
+
+        Network {
+            open_costs: vec![1; labels.len()],
+            labels,
+            flow_rates,
+            tunnels,
+            costs,
+            useful,
+        }
+    }
 
-        for (label, costs) in valve_costs {
-            valves.get_mut(&label).unwrap().costs = costs;
+    /// The rooms passed through on a shortest walk between two valves, excluding from.
+    fn walk(&self, from: usize, to: usize) -> Vec<usize> {
+        let mut walk = vec![];
+        let mut pos = from;
+        while pos != to {
+            // Any tunnel that gets closer is on a shortest walk
+            pos = *self.tunnels[pos]
+                .iter()
+                .find(|next| self.costs[**next][to] == self.costs[pos][to] - 1)
+                .unwrap();
+            walk.push(pos);
         }
 
-        valves
+        walk
     }
-}
 
-fn find_best(valves: &HashMap<String, Valve>) {
-    let mut best = State {
-        turned_on: BTreeSet::new(),
-        total: 0,
-        plumbers: vec![Plumber {
-            current_room: String::from("AA"),
-            previous_room: String::from(""),
-            remaining: 30,
-            path: String::from("AA"),
-        }],
-    };
+    fn valve(&self, label: &str) -> usize {
+        self.labels
+            .iter()
+            .position(|valve| valve == label)
+            .unwrap_or_else(|| panic!("No valve {}", label))
+    }
 
-    let mut stack = vec![best.clone()];
-    while let Some(next) = stack.pop() {
-        let moves = next.get_moves(valves, 0);
+    /// The most pressure that can be released from pos with time left, leaving the valves in the
+    /// open mask alone. It takes time to open a valve, so each move is to a closed valve and
+    /// opens it.
+    fn best(
+        &self,
+        pos: usize,
+        time: i32,
+        open: u64,
+        memo: &mut HashMap<(usize, i32, u64), i32>,
+    ) -> i32 {
+        if let Some(best) = memo.get(&(pos, time, open)) {
+            return *best;
+        }
+
+        let mut best = 0;
+        for (bit, dest) in self.useful.iter().enumerate() {
+            let remaining = time - self.costs[pos][*dest] - self.open_costs[*dest];
+            if open & (1 << bit) != 0 || remaining <= 0 {
+                continue;
+            }
+
+            let total = remaining * self.flow_rates[*dest]
+                + self.best(*dest, remaining, open | (1 << bit), memo);
+            best = best.max(total);
+        }
+
+        memo.insert((pos, time, open), best);
+        best
+    }
+
+    /// The valves an agent opens to release the memoised best, leaving the valves in the open
+    /// mask alone. Each step takes the first valve that keeps to the best.
+    fn schedule(
+        &self,
+        agent: &Agent,
+        open: u64,
+        memo: &mut HashMap<(usize, i32, u64), i32>,
+    ) -> Vec<Opening> {
+        let (mut pos, mut time, mut open) = (agent.start, agent.time, open);
+        let mut schedule = vec![];
+
+        loop {
+            let best = self.best(pos, time, open, memo);
+            let next = self.useful.iter().enumerate().find(|(bit, dest)| {
+                let remaining = time - self.costs[pos][**dest] - self.open_costs[**dest];
+                open & (1 << bit) == 0
+                    && remaining > 0
+                    && remaining * self.flow_rates[**dest]
+                        + self.best(**dest, remaining, open | (1 << bit), memo)
+                        == best
+            });
+            let Some((bit, dest)) = next else {
+                break;
+            };
+
+            time -= self.costs[pos][*dest] + self.open_costs[*dest];
+            schedule.push(Opening {
+                valve: *dest,
+                minute: agent.time - time,
+                released: time * self.flow_rates[*dest],
+            });
+            pos = *dest;
+            open |= 1 << bit;
+        }
+
+        schedule
+    }
+
+    /// The best single agent total for every mask of valves, opening only valves in the mask.
+    /// Sharing valves between agents needs the best total for every set of valves, not just the
+    /// best from the start that the memoised best gives, so every route is walked instead.
+    fn best_per_mask(&self, agent: &Agent) -> Vec<i32> {
+        let masks = 1 << self.useful.len();
+        let mut best = vec![0; masks];
+
+        // Record the best total for the exact set of valves each route opens
+        let mut stack = vec![(agent.start, agent.time, 0_u64, 0)];
+        while let Some((pos, time, open, total)) = stack.pop() {
+            best[open as usize] = best[open as usize].max(total);
+
+            for (bit, dest) in self.useful.iter().enumerate() {
+                let remaining = time - self.costs[pos][*dest] - self.open_costs[*dest];
+                if open & (1 << bit) == 0 && remaining > 0 {
+                    stack.push((
+                        *dest,
+                        remaining,
+                        open | (1 << bit),
+                        total + remaining * self.flow_rates[*dest],
+                    ));
+                }
+            }
+        }
 
-        for mv in moves {
-            if mv.plumbers[0].remaining > 0 {
-                stack.push(mv.clone());
+        // Subset maximum transform, so each mask holds the best over all its subsets
+        for bit in 0..self.useful.len() {
+            for mask in 0..masks {
+                if mask & (1 << bit) != 0 {
+                    best[mask] = best[mask].max(best[mask ^ (1 << bit)]);
+                }
             }
+        }
+
+        best
+    }
+
+    /// Finds the best plan for any number of agents working at once. They each open a disjoint
+    /// set of valves, so the valves are split between them one agent at a time, keeping the best
+    /// total for every set of valves handed out so far.
+    fn solve(&self, agents: &[Agent]) -> Result<Plan, String> {
+        if self.useful.len() > MAX_SHARED {
+            return Err(format!(
+                "Too many valves with flow to share between agents, {} when the most is {}",
+                self.useful.len(),
+                MAX_SHARED
+            ));
+        }
 
-            if mv.total > best.total {
-                best = mv;
-                println!("Best solution: {:?}", best);
+        let tables = agents
+            .par_iter()
+            .map(|agent| self.best_per_mask(agent))
+            .collect::<Vec<_>>();
+        let masks = 1 << self.useful.len();
+
+        // For each agent, the best total and the agent's own share of every set of valves
+        let mut totals = tables[0].clone();
+        let mut shares = vec![(0..masks).collect::<Vec<_>>()];
+        for table in &tables[1..] {
+            let mut next = vec![0; masks];
+            let mut share = vec![0; masks];
+
+            for mask in 0..masks {
+                // Every submask of mask
+                let mut sub = mask;
+                loop {
+                    let total = table[sub] + totals[mask ^ sub];
+                    if total > next[mask] {
+                        next[mask] = total;
+                        share[mask] = sub;
+                    }
+
+                    if sub == 0 {
+                        break;
+                    }
+                    sub = (sub - 1) & mask;
+                }
             }
+
+            totals = next;
+            shares.push(share);
+        }
+
+        // Walk back through the shares, each agent's route is the best leaving the other
+        // valves alone
+        let mut mask = masks - 1;
+        let mut schedules = vec![vec![]; agents.len()];
+        let mut memo = HashMap::new();
+        for agent_idx in (0..agents.len()).rev() {
+            let share = shares[agent_idx][mask];
+            let others = (masks - 1 - share) as u64;
+            schedules[agent_idx] = self.schedule(&agents[agent_idx], others, &mut memo);
+            mask ^= share;
         }
+
+        Ok(Plan {
+            total: totals[masks - 1],
+            schedules,
+        })
     }
 }
 
-#[allow(dead_code)]
-fn find_best_with_elephant_slow(valves: &HashMap<String, Valve>) {
-    let mut best = State {
-        turned_on: BTreeSet::new(),
-        total: 0,
-        plumbers: vec![
-            Plumber {
-                current_room: String::from("AA"),
-                previous_room: String::from(""),
-                remaining: 26,
-                path: String::from("AA"),
-            },
-            Plumber {
-                current_room: String::from("AA"),
-                previous_room: String::from(""),
-                remaining: 26,
-                path: String::from("AA"),
-            },
-        ],
-    };
+/// Someone opening valves, from their start room with the minutes they have.
+struct Agent {
+    start: usize,
+    time: i32,
+}
 
-    let mut stack = vec![best.clone()];
-    while let Some(next) = stack.pop() {
-        let plumber_idx = if next.plumbers[0].remaining > next.plumbers[1].remaining {
-            0
-        } else {
-            1
-        };
+/// A valve being opened at the end of the given minute, and what it releases from then on.
+#[derive(Debug, Clone)]
+struct Opening {
+    valve: usize,
+    minute: i32,
+    released: i32,
+}
+
+struct Plan {
+    total: i32,
+    /// The valves each agent opens, in order
+    schedules: Vec<Vec<Opening>>,
+}
 
-        let moves = next.get_moves(valves, plumber_idx);
+/// What an agent does in a minute.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Move(usize),
+    Open,
+    Wait,
+}
+
+/// The valves open during a minute, what they released and what each agent did.
+struct Minute {
+    open: Vec<usize>,
+    released: i32,
+    actions: Vec<Option<Action>>,
+}
+
+struct Replay {
+    total: i32,
+    minutes: Vec<Minute>,
+}
+
+impl Network {
+    /// The minute by minute actions for an agent following a schedule of openings.
+    fn actions(&self, agent: &Agent, schedule: &[Opening]) -> Vec<Action> {
+        let mut actions = vec![];
+        let mut pos = agent.start;
+        for opening in schedule {
+            actions.extend(self.walk(pos, opening.valve).into_iter().map(Action::Move));
+            actions.extend((0..self.open_costs[opening.valve]).map(|_| Action::Open));
+            pos = opening.valve;
+        }
+        actions.resize(agent.time as usize, Action::Wait);
+
+        actions
+    }
+
+    /// Plays the agents' actions against the network, checking every move is through a tunnel
+    /// and every valve is only opened once. A valve opens once an agent has spent its opening
+    /// cost in minutes on it in a row, and stopping before then is an error. All agents finish at the same time, so agents with less
+    /// time start later.
+    fn replay(&self, agents: &[Agent], actions: &[Vec<Action>]) -> Result<Replay, String> {
+        let end = agents.iter().map(|agent| agent.time).max().unwrap_or(0);
+        let mut positions = agents.iter().map(|agent| agent.start).collect::<Vec<_>>();
+        let mut opening = vec![0; agents.len()];
+        let mut open: Vec<usize> = vec![];
+        let mut total = 0;
+        let mut minutes = vec![];
+
+        for minute in 1..=end {
+            let released = open
+                .iter()
+                .map(|valve| self.flow_rates[*valve])
+                .sum::<i32>();
+            let mut minute_actions = vec![];
+            let mut opened = vec![];
+
+            for (agent_idx, agent) in agents.iter().enumerate() {
+                let first_minute = end - agent.time + 1;
+                let action = if minute < first_minute {
+                    None
+                } else {
+                    let step = (minute - first_minute) as usize;
+                    Some(*actions[agent_idx].get(step).unwrap_or(&Action::Wait))
+                };
+
+                let pos = positions[agent_idx];
+                let fail = |reason: String| {
+                    Err(format!(
+                        "Minute {}, agent {}: {}",
+                        minute, agent_idx, reason
+                    ))
+                };
+                if opening[agent_idx] > 0 && action != Some(Action::Open) {
+                    return fail(format!(
+                        "stopped opening {} after {} of {} minutes",
+                        self.labels[pos], opening[agent_idx], self.open_costs[pos]
+                    ));
+                }
+                match action {
+                    Some(Action::Move(dest)) => {
+                        if !self.tunnels[pos].contains(&dest) {
+                            return fail(format!(
+                                "no tunnel from {} to {}",
+                                self.labels[pos], self.labels[dest]
+                            ));
+                        }
+                        positions[agent_idx] = dest;
+                    }
+                    Some(Action::Open) => {
+                        if open.contains(&pos) || opened.contains(&pos) {
+                            return fail(format!("{} is already open", self.labels[pos]));
+                        }
+                        opening[agent_idx] += 1;
+                        if opening[agent_idx] == self.open_costs[pos] {
+                            opened.push(pos);
+                            opening[agent_idx] = 0;
+                        }
+                    }
+                    Some(Action::Wait) | None => {}
+                }
 
-        for mv in moves {
-            if mv.plumbers[0].remaining > 0 {
-                stack.push(mv.clone());
+                minute_actions.push(action);
             }
 
-            if mv.total > best.total {
-                best = mv;
-                println!("Best solution: {:?}", best);
+            total += released;
+            minutes.push(Minute {
+                open: open.clone(),
+                released,
+                actions: minute_actions,
+            });
+            open.extend(opened);
+        }
+
+        for (agent_idx, agent) in agents.iter().enumerate() {
+            if opening[agent_idx] > 0 {
+                let pos = positions[agent_idx];
+                return Err(format!(
+                    "Agent {} is still opening {} when time runs out, after {} of {} minutes",
+                    agent_idx, self.labels[pos], opening[agent_idx], self.open_costs[pos]
+                ));
+            }
+            if actions[agent_idx].len() > agent.time as usize {
+                return Err(format!(
+                    "Agent {} has more than {} minutes of actions",
+                    agent_idx, agent.time
+                ));
             }
         }
+
+        Ok(Replay { total, minutes })
     }
-}
 
-fn find_best_with_elephant(valves: &HashMap<String, Valve>) {
-    // Find all possible single path solutions
-    let start = State {
-        turned_on: BTreeSet::new(),
-        total: 0,
-        plumbers: vec![Plumber {
-            current_room: String::from("AA"),
-            previous_room: String::from(""),
-            remaining: 26,
-            path: String::from("AA"),
-        }],
-    };
+    /// One line per agent: the start room and time, then the rooms moved to, "open" and "wait".
+    fn format_schedule(&self, agents: &[Agent], actions: &[Vec<Action>]) -> String {
+        agents
+            .iter()
+            .zip(actions)
+            .map(|(agent, actions)| {
+                // Trailing waits don't change anything
+                let used = actions
+                    .iter()
+                    .rposition(|action| *action != Action::Wait)
+                    .map_or(0, |idx| idx + 1);
+                let steps = actions[..used]
+                    .iter()
+                    .map(|action| match action {
+                        Action::Move(dest) => self.labels[*dest].clone(),
+                        Action::Open => "open".to_string(),
+                        Action::Wait => "wait".to_string(),
+                    })
+                    .collect::<Vec<_>>();
+
+                format!(
+                    "{} {}: {}",
+                    self.labels[agent.start],
+                    agent.time,
+                    steps.join(" ")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Reads schedules written by format_schedule, blank lines and lines starting # are skipped.
+    fn parse_schedule(&self, input: &str) -> Result<(Vec<Agent>, Vec<Vec<Action>>), String> {
+        let mut agents = vec![];
+        let mut actions = vec![];
 
-    let mut best_paths: HashMap<BTreeSet<String>, State> = HashMap::new();
-    let mut stack = vec![start];
-    while let Some(next) = stack.pop() {
-        let moves = next.get_moves(valves, 0);
+        for line in input.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (agent, steps) = line
+                .split_once(':')
+                .ok_or(format!("Missing ':' in '{}'", line))?;
+            let (room, time) = agent
+                .trim()
+                .split_once(' ')
+                .ok_or(format!("Expected 'ROOM TIME' in '{}'", line))?;
+            let find = |label: &str| {
+                self.labels
+                    .iter()
+                    .position(|valve| valve == label)
+                    .ok_or(format!("No valve {}", label))
+            };
+
+            agents.push(Agent {
+                start: find(room)?,
+                time: time
+                    .trim()
+                    .parse::<i32>()
+                    .map_err(|_| format!("Invalid time '{}'", time))?,
+            });
+            actions.push(
+                steps
+                    .split_whitespace()
+                    .map(|step| match step {
+                        "open" => Ok(Action::Open),
+                        "wait" => Ok(Action::Wait),
+                        label => find(label).map(Action::Move),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            );
+        }
 
-        for mv in moves {
-            if mv.plumbers[0].remaining > 0 {
-                stack.push(mv.clone());
+        Ok((agents, actions))
+    }
+
+    fn print_timeline(&self, replay: &Replay) {
+        for (minute_idx, minute) in replay.minutes.iter().enumerate() {
+            println!("== Minute {} ==", minute_idx + 1);
+
+            let open = minute
+                .open
+                .iter()
+                .map(|valve| self.labels[*valve].as_str())
+                .collect::<Vec<_>>();
+            if open.is_empty() {
+                println!("No valves are open.");
+            } else {
+                println!(
+                    "Valves {} are open, releasing {} pressure.",
+                    open.join(", "),
+                    minute.released
+                );
             }
 
-            if !best_paths.contains_key(&mv.turned_on) || best_paths[&mv.turned_on].total < mv.total
-            {
-                best_paths.insert(mv.turned_on.clone(), mv.clone());
+            for (agent_idx, action) in minute.actions.iter().enumerate() {
+                match action {
+                    Some(Action::Move(dest)) => {
+                        println!("Agent {} moves to valve {}.", agent_idx, self.labels[*dest])
+                    }
+                    Some(Action::Open) => println!("Agent {} opens a valve.", agent_idx),
+                    Some(Action::Wait) => println!("Agent {} waits.", agent_idx),
+                    None => println!("Agent {} is being taught.", agent_idx),
+                }
             }
+            println!();
         }
     }
+}
 
-    // Find the best combination paths that don't use the same valves
-    let mut best = 0;
-    for (path_a, state_a) in &best_paths {
-        for (path_b, state_b) in &best_paths {
-            if path_a.is_disjoint(path_b) {
-                let total = state_a.total + state_b.total;
-                best = best.max(total);
+const ROUTE_COLOURS: [&str; 4] = ["red", "blue", "darkgreen", "darkorange"];
+
+impl Network {
+    /// Graphviz DOT for the tunnels between every room. Each route is a sequence of rooms, and
+    /// the tunnels along it are coloured.
+    fn tunnels_dot(&self, routes: &[Vec<usize>]) -> String {
+        let mut dot = String::from("graph tunnels {\n");
+        for (valve, label) in self.labels.iter().enumerate() {
+            let style = if self.flow_rates[valve] > 0 {
+                ", style=filled, fillcolor=lightyellow"
+            } else {
+                ""
+            };
+            dot += &format!(
+                "  {} [label=\"{}\\n{}\"{}];\n",
+                label, label, self.flow_rates[valve], style
+            );
+        }
+
+        for (from, tunnels) in self.tunnels.iter().enumerate() {
+            for to in tunnels.iter().filter(|to| **to > from) {
+                let style = route_style(routes, from, *to)
+                    .map_or(String::new(), |style| format!(" [{}]", style));
+                dot += &format!(
+                    "  {} -- {}{};\n",
+                    self.labels[from], self.labels[*to], style
+                );
             }
         }
+
+        dot + "}\n"
+    }
+
+    /// Graphviz DOT for the valves worth opening and the start room, with an edge weighted by
+    /// the minutes between every pair. Each route is a sequence of these valves.
+    fn compressed_dot(&self, start: usize, routes: &[Vec<usize>]) -> String {
+        let mut valves = self.useful.clone();
+        if !valves.contains(&start) {
+            valves.insert(0, start);
+        }
+
+        let mut dot = String::from("graph valves {\n");
+        for valve in &valves {
+            dot += &format!(
+                "  {} [label=\"{}\\n{}\"];\n",
+                self.labels[*valve], self.labels[*valve], self.flow_rates[*valve]
+            );
+        }
+
+        for (idx, from) in valves.iter().enumerate() {
+            for to in &valves[idx + 1..] {
+                let style = route_style(routes, *from, *to)
+                    .map_or(String::new(), |style| format!(", {}", style));
+                dot += &format!(
+                    "  {} -- {} [label={}{}];\n",
+                    self.labels[*from], self.labels[*to], self.costs[*from][*to], style
+                );
+            }
+        }
+
+        dot + "}\n"
+    }
+}
+
+/// The DOT attributes for an edge, coloured by the first route that uses it.
+fn route_style(routes: &[Vec<usize>], from: usize, to: usize) -> Option<String> {
+    let agent = routes.iter().position(|route| {
+        route
+            .windows(2)
+            .any(|step| (step[0], step[1]) == (from, to) || (step[0], step[1]) == (to, from))
+    });
+
+    agent.map(|agent| {
+        format!(
+            "color={}, penwidth=3",
+            ROUTE_COLOURS[agent % ROUTE_COLOURS.len()]
+        )
+    })
+}
+
+/// Prints the plan as a schedule that can be replayed, checking it releases what was expected.
+fn print_plan(network: &Network, agents: &[Agent], plan: &Plan, timeline: bool) {
+    let actions = agents
+        .iter()
+        .zip(&plan.schedules)
+        .map(|(agent, schedule)| network.actions(agent, schedule))
+        .collect::<Vec<_>>();
+
+    let replay = network.replay(agents, &actions).unwrap();
+    assert_eq!(replay.total, plan.total, "Plan doesn't replay");
+
+    if timeline {
+        network.print_timeline(&replay);
+    }
+
+    for (agent_idx, schedule) in plan.schedules.iter().enumerate() {
+        let openings = schedule
+            .iter()
+            .map(|opening| {
+                format!(
+                    "{}@{} (+{})",
+                    network.labels[opening.valve], opening.minute, opening.released
+                )
+            })
+            .collect::<Vec<_>>();
+        println!("Agent {} opens {}", agent_idx, openings.join(", "));
+    }
+    println!("{}", network.format_schedule(agents, &actions));
+}
+
+/// The start room and minutes available, and how many of those minutes it takes to teach the
+/// elephant.
+struct Scenario {
+    start: usize,
+    time: i32,
+    teaching: i32,
+}
+
+fn find_best(network: &Network, scenario: &Scenario, timeline: bool) -> (Vec<Agent>, Plan) {
+    let agent = Agent {
+        start: scenario.start,
+        time: scenario.time,
+    };
+    let mut memo = HashMap::new();
+    let best = network.best(agent.start, agent.time, 0, &mut memo);
+
+    // One agent has no valves to share, so the plan comes straight from the memo
+    let plan = Plan {
+        total: best,
+        schedules: vec![network.schedule(&agent, 0, &mut memo)],
+    };
+    let agents = vec![agent];
+
+    print_plan(network, &agents, &plan, timeline);
+    println!("Best total: {}", best);
+
+    (agents, plan)
+}
+
+fn find_best_with_elephant(
+    network: &Network,
+    scenario: &Scenario,
+    timeline: bool,
+) -> Option<(Vec<Agent>, Plan)> {
+    if scenario.teaching >= scenario.time {
+        println!("No time left after teaching the elephant");
+        return None;
     }
 
-    println!("Best total with 2: {}", best);
+    let agent = || Agent {
+        start: scenario.start,
+        time: scenario.time - scenario.teaching,
+    };
+    let agents = vec![agent(), agent()];
+    let plan = match network.solve(&agents) {
+        Ok(plan) => plan,
+        Err(err) => {
+            println!("{}", err);
+            return None;
+        }
+    };
+
+    print_plan(network, &agents, &plan, timeline);
+    println!("Best total with 2: {}", plan.total);
+
+    Some((agents, plan))
+}
+
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|idx| args.get(idx + 1))
+        .cloned()
 }
 
+/// Options:
+/// --start ROOM        the room everyone starts in, defaults to AA
+/// --time N            minutes before the volcano erupts, defaults to 30
+/// --teaching N        minutes spent teaching the elephant, defaults to 4
+/// --open-cost ROOM:N  minutes to open the valve in ROOM, defaults to 1, can be given more than once
+/// --agent ROOM:TIME   add an agent starting in ROOM with TIME minutes, can be given more than
+///                     once to find the best plan for all of them together
+/// --timeline          print the plans minute by minute
+/// --replay FILE       check a schedule in the format the plans are printed in and report its total
+/// --dot FILE          save the tunnels between every room as Graphviz DOT
+/// --valves-dot FILE   save the valves worth opening and the minutes between them as DOT
+/// --highlight a|b     colour the route of the part A or B plan, or of the --agent plan, in the DOT
 fn main() {
     let start = time::SystemTime::now();
 
     let input = include_str!("../../assets/day16.txt");
-    let valves = Valve::from(input);
+    let mut network = Network::from(input);
 
-    find_best(&valves);
+    let args = std::env::args().collect::<Vec<_>>();
+    let timeline = args.iter().any(|arg| arg == "--timeline");
+    let num_arg = |name: &str, default: i32| {
+        arg_value(&args, name).map_or(default, |val| val.parse::<i32>().unwrap())
+    };
+
+    for opt in args.windows(2).filter(|opt| opt[0] == "--open-cost") {
+        let (room, cost) = opt[1].split_once(':').unwrap();
+        let cost = cost.parse::<i32>().unwrap();
+        assert!(cost > 0, "Opening a valve takes at least a minute");
+        let valve = network.valve(room);
+        network.open_costs[valve] = cost;
+    }
+
+    let scenario = Scenario {
+        start: network.valve(&arg_value(&args, "--start").unwrap_or("AA".to_string())),
+        time: num_arg("--time", 30),
+        teaching: num_arg("--teaching", 4),
+    };
+
+    let part_a = find_best(&network, &scenario, timeline);
 
     println!();
 
-    find_best_with_elephant(&valves);
+    let part_b = find_best_with_elephant(&network, &scenario, timeline);
+    let mut highlight = match arg_value(&args, "--highlight").as_deref() {
+        Some("a") => Some(part_a),
+        Some("b") => part_b,
+        _ => None,
+    };
+
+    let agents = args
+        .windows(2)
+        .filter(|opt| opt[0] == "--agent")
+        .map(|opt| {
+            let (room, time) = opt[1].split_once(':').unwrap();
+            Agent {
+                start: network.valve(room),
+                time: time.parse::<i32>().unwrap(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if !agents.is_empty() {
+        println!();
+
+        match network.solve(&agents) {
+            Ok(plan) => {
+                print_plan(&network, &agents, &plan, timeline);
+                println!("Best total with {}: {}", agents.len(), plan.total);
+
+                if highlight.is_some() {
+                    highlight = Some((agents, plan));
+                }
+            }
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    let (walks, openings) = match &highlight {
+        Some((agents, plan)) => agents
+            .iter()
+            .zip(&plan.schedules)
+            .map(|(agent, schedule)| {
+                let mut walk = vec![agent.start];
+                walk.extend(network.actions(agent, schedule).iter().filter_map(
+                    |action| match action {
+                        Action::Move(dest) => Some(*dest),
+                        _ => None,
+                    },
+                ));
+
+                let mut openings = vec![agent.start];
+                openings.extend(schedule.iter().map(|opening| opening.valve));
+
+                (walk, openings)
+            })
+            .unzip(),
+        None => (vec![], vec![]),
+    };
+    if let Some(path) = arg_value(&args, "--dot") {
+        std::fs::write(path, network.tunnels_dot(&walks)).unwrap();
+    }
+    if let Some(path) = arg_value(&args, "--valves-dot") {
+        std::fs::write(path, network.compressed_dot(scenario.start, &openings)).unwrap();
+    }
+
+    if let Some(idx) = args.iter().position(|arg| arg == "--replay") {
+        println!();
+
+        let schedule = std::fs::read_to_string(&args[idx + 1]).unwrap();
+        let replayed = network
+            .parse_schedule(&schedule)
+            .and_then(|(agents, actions)| network.replay(&agents, &actions));
+        match replayed {
+            Ok(replay) => {
+                if timeline {
+                    network.print_timeline(&replay);
+                }
+                println!("Replayed total: {}", replay.total);
+            }
+            Err(err) => println!("Invalid schedule: {}", err),
+        }
+    }
 
     let end = time::SystemTime::now();
     println!("Took {:?}", end.duration_since(start));
@@ -27,195 +27,283 @@ use std::collections::HashMap;
 /// A rock is push as soon as it appears and before it falls the first unit.
 /// Part A:
 /// How tall will the tower of rocks be after 2022 rocks have fallen?
-
+/// Part B:
+/// How tall will it be after 1000000000000 rocks?
 const GRID_WIDTH: i64 = 7;
 const COL_GAP: i64 = 2;
 const ROW_GAP: i64 = 3;
 const EMPTY_CELL: char = '.';
-const SHAPE_CELL: char = 'X';
+const SHAPE_CELL: char = '@';
 const FULL_CELL: char = '#';
-
-#[derive(Clone)]
-struct Piece {
-    row: i64,
-    col: i64,
-    shape: Vec<Vec<char>>, // indexed row,col
+/// Rows kept before looking for ones that can't be reached any more
+const PRUNE_ROWS: usize = 128;
+/// The shapes in the order they fall, separated by blank lines
+const SHAPES: &str = "####
+
+.#.
+###
+.#.
+
+..#
+..#
+###
+
+#
+#
+#
+#
+
+##
+##";
+
+/// Rows are bitmasks with bit n set when column n (from the left wall) is full.
+struct Shape {
+    /// Indexed from the bottom, with the left of the shape in bit 0
+    rows: Vec<u64>,
+    width: i64,
 }
 
-impl Piece {
-    fn can_place(&self, grid: &Grid, row: i64, col: i64) -> bool {
-        // The piece is outside the side of the grid
-        if !(0..GRID_WIDTH + 1).contains(&col)
-            || !(0..GRID_WIDTH + 1).contains(&(col + self.width()))
-        {
-            return false;
-        }
-
-        // The piece is off the bottom of the grid
-        if row < 0 {
-            return false;
-        }
-
-        // The piece is higher than the tower so can't hit anything
-        if row >= grid.tower_height() {
-            return true;
-        }
-
-        // Check all cells in the grid
-        for piece_row_idx in 0..self.shape.len() {
-            for piece_col_idx in 0..self.shape[piece_row_idx].len() {
-                let grid_row_idx = piece_row_idx + row as usize;
-                let grid_col_idx = piece_col_idx + col as usize;
-
-                if grid_row_idx >= grid.data.len() {
-                    continue;
-                }
-                if self.shape[piece_row_idx][piece_col_idx] == SHAPE_CELL
-                    && grid.data[grid_row_idx][grid_col_idx] == FULL_CELL
-                {
-                    return false;
-                }
+/// Reads shapes drawn with # for rock and . for air, top row first, separated by blank lines.
+fn parse_shapes(input: &str) -> Result<Vec<Shape>, String> {
+    input
+        .replace("\r\n", "\n")
+        .split("\n\n")
+        .filter(|picture| !picture.trim().is_empty())
+        .map(|picture| {
+            let lines = picture.trim().lines().map(str::trim).collect::<Vec<_>>();
+            let width = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+            if width > 64 {
+                return Err(format!("Shape wider than 64:\n{}", picture));
             }
-        }
 
-        true
-    }
+            let rows = lines
+                .iter()
+                .rev()
+                .map(|line| {
+                    line.chars()
+                        .enumerate()
+                        .try_fold(0, |mask, (col, cell)| match cell {
+                            FULL_CELL => Ok(mask | (1 << col)),
+                            EMPTY_CELL => Ok(mask),
+                            _ => Err(format!("Unexpected '{}' in shape:\n{}", cell, picture)),
+                        })
+                })
+                .collect::<Result<Vec<u64>, _>>()?;
+            if rows.iter().all(|row| *row == 0) {
+                return Err(format!("Shape has no rock:\n{}", picture));
+            }
 
-    fn width(&self) -> i64 {
-        self.shape[0].len() as i64
-    }
+            Ok(Shape {
+                rows,
+                width: width as i64,
+            })
+        })
+        .collect()
+}
 
-    fn height(&self) -> i64 {
-        self.shape.len() as i64
-    }
+/// The chamber width, where rocks appear and the shapes that fall in turn.
+struct Rules {
+    width: i64,
+    col_gap: i64,
+    row_gap: i64,
+    shapes: Vec<Shape>,
+}
 
-    fn new(count: i64, tower_height: i64) -> Self {
-        let idx = count % 5;
-
-        if idx == 0 {
-            return Piece::new_row(tower_height);
-        } else if idx == 1 {
-            return Piece::new_plus(tower_height);
-        } else if idx == 2 {
-            return Piece::new_l(tower_height);
-        } else if idx == 3 {
-            return Piece::new_col(tower_height);
-        } else if idx == 4 {
-            return Piece::new_square(tower_height);
+impl Rules {
+    fn check(&self) -> Result<(), String> {
+        if !(1..=64).contains(&self.width) {
+            return Err(format!("Width {} is not between 1 and 64", self.width));
+        }
+        if self.shapes.is_empty() {
+            return Err("No shapes".to_string());
+        }
+        if self.col_gap < 0 || self.row_gap < 0 {
+            return Err(format!(
+                "Gaps {} from the wall and {} above the tower can't be negative",
+                self.col_gap, self.row_gap
+            ));
+        }
+        if let Some(shape) = self
+            .shapes
+            .iter()
+            .find(|shape| self.col_gap + shape.width > self.width)
+        {
+            return Err(format!(
+                "A shape {} wide doesn't fit {} from the wall of a chamber {} wide",
+                shape.width, self.col_gap, self.width
+            ));
         }
 
-        unreachable!("This shouldn't happen");
+        Ok(())
     }
 
-    fn new_row(tower_height: i64) -> Self {
-        Piece {
-            row: tower_height + ROW_GAP,
-            col: COL_GAP,
-            shape: vec![vec![SHAPE_CELL; 4]],
-        }
+    /// Every column of a row filled
+    fn full_row(&self) -> u64 {
+        u64::MAX >> (64 - self.width)
     }
+}
 
-    fn new_plus(tower_height: i64) -> Self {
-        Piece {
-            row: tower_height + ROW_GAP,
-            col: COL_GAP,
-            shape: vec![
-                vec![EMPTY_CELL, SHAPE_CELL, EMPTY_CELL],
-                vec![SHAPE_CELL, SHAPE_CELL, SHAPE_CELL],
-                vec![EMPTY_CELL, SHAPE_CELL, EMPTY_CELL],
-            ],
-        }
-    }
+struct Piece<'a> {
+    row: i64,
+    col: i64,
+    shape: &'a Shape,
+}
 
-    fn new_l(tower_height: i64) -> Self {
-        Piece {
-            row: tower_height + ROW_GAP,
-            col: COL_GAP,
-            shape: vec![
-                vec![SHAPE_CELL, SHAPE_CELL, SHAPE_CELL],
-                vec![EMPTY_CELL, EMPTY_CELL, SHAPE_CELL],
-                vec![EMPTY_CELL, EMPTY_CELL, SHAPE_CELL],
-            ],
+impl<'a> Piece<'a> {
+    fn can_place(&self, grid: &Grid, row: i64, col: i64) -> bool {
+        // The piece is outside the side of the grid, or off the bottom
+        if col < 0 || col + self.shape.width > grid.width || row < 0 {
+            return false;
         }
+
+        self.shape
+            .rows
+            .iter()
+            .enumerate()
+            .all(|(idx, mask)| grid.row(row + idx as i64) & (mask << col) == 0)
     }
 
-    fn new_square(tower_height: i64) -> Self {
-        Piece {
-            row: tower_height + ROW_GAP,
-            col: COL_GAP,
-            shape: vec![vec![SHAPE_CELL; 2]; 2],
-        }
+    fn height(&self) -> i64 {
+        self.shape.rows.len() as i64
     }
 
-    fn new_col(tower_height: i64) -> Self {
+    /// The next shape in turn, row_gap above the tower.
+    fn new(rules: &'a Rules, count: i64, tower_height: i64) -> Self {
         Piece {
-            row: tower_height + ROW_GAP,
-            col: COL_GAP,
-            shape: vec![vec![SHAPE_CELL]; 4],
+            row: tower_height + rules.row_gap,
+            col: rules.col_gap,
+            shape: &rules.shapes[(count % rules.shapes.len() as i64) as usize],
         }
     }
 }
 
 #[derive(Clone)]
 struct Grid {
-    // indexed row,col
-    data: Vec<Vec<char>>,
+    /// The rows still in reach of falling pieces, indexed from the bottom
+    rows: Vec<u64>,
+    /// How many rows have been dropped from under the ones kept
+    base: i64,
+    height: i64,
+    width: i64,
+    full_row: u64,
+    /// How many rows to keep before pruning again
+    prune_at: usize,
 }
 
 impl Grid {
-    fn new() -> Self {
-        Grid { data: vec![] }
+    fn new(rules: &Rules) -> Self {
+        Grid {
+            rows: vec![],
+            base: 0,
+            height: 0,
+            width: rules.width,
+            full_row: rules.full_row(),
+            prune_at: 2 * PRUNE_ROWS,
+        }
     }
 
     fn tower_height(&self) -> i64 {
-        if self.data.is_empty() {
-            return 0;
+        self.height
+    }
+
+    /// The mask of full cells in a row, rows above the tower are empty.
+    fn row(&self, row: i64) -> u64 {
+        self.rows
+            .get((row - self.base) as usize)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn place(&mut self, piece: &Piece) {
+        let top = (piece.row + piece.height() - self.base) as usize;
+        if top > self.rows.len() {
+            self.rows.resize(top, 0);
         }
 
-        for (idx, row) in self.data.iter().enumerate() {
-            if !row.contains(&FULL_CELL) {
-                return (idx + 1) as i64;
-            }
+        for (idx, mask) in piece.shape.rows.iter().enumerate() {
+            self.rows[(piece.row - self.base) as usize + idx] |= mask << piece.col;
         }
+        self.height = self.height.max(piece.row + piece.height());
 
-        self.data.len() as i64
+        if self.rows.len() >= self.prune_at {
+            self.prune();
+            // Wide chambers can have gaps to the floor, so back off when little is dropped
+            self.prune_at = (2 * PRUNE_ROWS).max(2 * self.rows.len());
+        }
     }
 
-    fn place(&mut self, piece: &Piece) {
-        let new_height = piece.row as usize + piece.shape.len();
-        if new_height > self.data.len() {
-            self.data.extend(vec![
-                vec![EMPTY_CELL; GRID_WIDTH as usize];
-                new_height - self.data.len()
-            ]);
+    /// Drops the rows below the lowest one a falling cell could get to, keeping the row that
+    /// stops it.
+    fn prune(&mut self) {
+        let mut reach = self.full_row;
+        for idx in (0..self.rows.len()).rev() {
+            reach = self.spread(reach, self.rows[idx]);
+            if reach == 0 {
+                self.rows.drain(..idx);
+                self.base += idx as i64;
+                return;
+            }
         }
+    }
 
-        let (mut piece_row, mut piece_col) = (0, 0);
-
-        for grid_row in piece.row..piece.row + piece.height() {
-            for grid_col in piece.col..piece.col + piece.width() {
-                if piece.shape[piece_row][piece_col] == SHAPE_CELL {
-                    self.data[grid_row as usize][grid_col as usize] = FULL_CELL;
-                }
+    /// The cells of a row a falling cell could get to, from those it could get to in the row
+    /// above. Cells only move down or sideways, so reach spreads down a row and then along it.
+    fn spread(&self, above: u64, row: u64) -> u64 {
+        let free = !row & self.full_row;
+        let mut reach = above & free;
+        loop {
+            let spread = (reach | (reach << 1) | (reach >> 1)) & free;
+            if spread == reach {
+                return reach;
+            }
+            reach = spread;
+        }
+    }
 
-                piece_col += 1;
+    /// Every row from the top of the tower down to the one that stops falling cells, with the
+    /// cells no falling cell could get to filled in. This is all that matters for the rocks still
+    /// to fall, so two towers with the same surface grow the same way. None if falling cells can
+    /// get more than limit rows down.
+    fn surface(&self, limit: usize) -> Option<Vec<u64>> {
+        let mut surface = vec![];
+        let mut reach = self.full_row;
+        for row in (self.base..self.height).rev() {
+            if surface.len() == limit {
+                return None;
             }
 
-            piece_row += 1;
-            piece_col = 0;
+            reach = self.spread(reach, self.row(row));
+            surface.push(!reach & self.full_row);
+            if reach == 0 {
+                return Some(surface);
+            }
         }
+
+        // Otherwise the floor stops them
+        Some(surface)
     }
 
     #[allow(dead_code)]
     fn print(&self, piece: Option<Piece>) {
-        let mut placed = self.clone();
-        if let Some(p) = piece {
-            placed.place(&p)
-        }
-
-        for row in placed.data.iter().rev() {
-            for col in row {
-                print!("{}", &col);
+        for row in (self.base
+            ..self
+                .height
+                .max(piece.as_ref().map_or(0, |p| p.row + p.height())))
+            .rev()
+        {
+            let falling = piece
+                .as_ref()
+                .filter(|p| (p.row..p.row + p.height()).contains(&row))
+                .map_or(0, |p| p.shape.rows[(row - p.row) as usize] << p.col);
+
+            for col in 0..self.width {
+                if self.row(row) & (1 << col) != 0 {
+                    print!("{}", FULL_CELL);
+                } else if falling & (1 << col) != 0 {
+                    print!("{}", SHAPE_CELL);
+                } else {
+                    print!("{}", EMPTY_CELL);
+                }
             }
             println!();
         }
@@ -224,32 +312,45 @@ impl Grid {
     }
 }
 
-fn run(moves: &Vec<char>, rocks: i64) {
-    let mut grid = Grid::new();
+/// Drops rocks one at a time, blown by the jets.
+struct Simulation<'a> {
+    rules: &'a Rules,
+    moves: &'a [char],
+    grid: Grid,
+    move_idx: usize,
+    count: i64,
+}
 
-    let mut cycles = HashMap::new();
-    let mut from_cycle = 0;
+impl<'a> Simulation<'a> {
+    fn new(rules: &'a Rules, moves: &'a [char]) -> Self {
+        Simulation {
+            rules,
+            moves,
+            grid: Grid::new(rules),
+            move_idx: 0,
+            count: 0,
+        }
+    }
 
-    let mut move_idx = 0;
-    let mut count = 0;
-    while count < rocks {
-        let mut piece = Piece::new(count, grid.tower_height());
+    fn drop_rock(&mut self) {
+        let grid = &mut self.grid;
+        let mut piece = Piece::new(self.rules, self.count, grid.tower_height());
 
         loop {
-            let (gust_row, gust_col) = match moves[move_idx] {
+            let (gust_row, gust_col) = match self.moves[self.move_idx] {
                 '>' => (piece.row, piece.col + 1),
                 '<' => (piece.row, piece.col - 1),
                 _ => panic!("Invalid move"),
             };
-            move_idx = (move_idx + 1) % moves.len();
+            self.move_idx = (self.move_idx + 1) % self.moves.len();
 
-            if piece.can_place(&grid, gust_row, gust_col) {
+            if piece.can_place(grid, gust_row, gust_col) {
                 piece.row = gust_row;
                 piece.col = gust_col;
             }
 
             let (move_row, move_col) = (piece.row - 1, piece.col);
-            if piece.can_place(&grid, move_row, move_col) {
+            if piece.can_place(grid, move_row, move_col) {
                 piece.row = move_row;
                 piece.col = move_col;
             } else {
@@ -258,49 +359,174 @@ fn run(moves: &Vec<char>, rocks: i64) {
             }
         }
 
-        if from_cycle == 0 {
-            let piece_idx = count % 5;
-            let key = (piece_idx, move_idx);
+        self.count += 1;
+    }
 
-            if let Some((2, prev_count, prev_top)) = cycles.get(&key) {
-                println!(
-                    "Found cycle at rock {}, height {}",
-                    count,
-                    grid.tower_height()
-                );
+    /// The next piece, the next jet and the surface of the tower, which together decide
+    /// everything that happens next. None if the surface is too deep to bother keeping.
+    fn key(&self) -> Option<(i64, usize, Vec<u64>)> {
+        let surface = self.grid.surface(2 * PRUNE_ROWS)?;
+        Some((
+            self.count % self.rules.shapes.len() as i64,
+            self.move_idx,
+            surface,
+        ))
+    }
+}
 
-                let count_diff = count - prev_count + 1;
-                let top_diff = grid.tower_height() - prev_top;
-                let repeats = (rocks - count) / count_diff;
+/// From rock start on, every length rocks add gain to the height of the tower.
+struct Cycle {
+    start: i64,
+    length: i64,
+    gain: i64,
+    /// The height after each number of rocks, up to the end of the first cycle
+    heights: Vec<i64>,
+}
 
-                from_cycle = repeats * top_diff;
-                count += repeats * count_diff;
-                count += 1;
-                println!("Skipping {} height to rock {}", from_cycle, count);
-            } else {
-                count += 1;
-            }
+impl Cycle {
+    fn height_after(&self, rocks: i64) -> i64 {
+        if rocks < self.heights.len() as i64 {
+            return self.heights[rocks as usize];
+        }
 
-            cycles
-                .entry(key)
-                .and_modify(|(occurrence, prev_count, prev_top)| {
-                    *occurrence += 1;
-                    *prev_count = count;
-                    *prev_top = grid.tower_height();
-                })
-                .or_insert((1, count, grid.tower_height()));
-        } else {
-            count += 1;
+        let cycles = (rocks - self.start) / self.length;
+        let rest = (rocks - self.start) % self.length;
+        self.heights[(self.start + rest) as usize] + cycles * self.gain
+    }
+}
+
+/// Drops rocks until the state before a rock matches the state before an earlier one. The state
+/// is exact, so from then on the rocks in between repeat for ever.
+fn find_cycle(rules: &Rules, moves: &[char], max_rocks: i64) -> Option<Cycle> {
+    let mut sim = Simulation::new(rules, moves);
+    let mut seen = HashMap::new();
+    let mut heights = vec![0];
+
+    while sim.count < max_rocks {
+        if let Some(key) = sim.key() {
+            if let Some(prev) = seen.insert(key, sim.count) {
+                return Some(Cycle {
+                    start: prev,
+                    length: sim.count - prev,
+                    gain: sim.grid.tower_height() - heights[prev as usize],
+                    heights,
+                });
+            }
         }
+
+        sim.drop_rock();
+        heights.push(sim.grid.tower_height());
+    }
+
+    None
+}
+
+/// The height of the tower after dropping every rock.
+fn simulate(rules: &Rules, moves: &[char], rocks: i64) -> i64 {
+    let mut sim = Simulation::new(rules, moves);
+    while sim.count < rocks {
+        sim.drop_rock();
     }
 
-    println!("Tower height {}", grid.tower_height() + from_cycle);
+    sim.grid.tower_height()
 }
 
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|idx| args.get(idx + 1))
+        .cloned()
+}
+
+/// Options:
+/// --rocks N       also find the tower height after N rocks, can be given more than once
+/// --shapes FILE   the shapes that fall, drawn as in SHAPES
+/// --width N       chamber width up to 64, defaults to 7
+/// --col-gap N     columns between the left wall and a new rock, defaults to 2
+/// --row-gap N     rows between the tower and a new rock, defaults to 3
 fn main() {
     let input = include_str!("../../assets/day17.txt");
-    let moves = input.chars().collect::<Vec<_>>();
+    let moves = input.trim().chars().collect::<Vec<_>>();
+
+    let args = std::env::args().collect::<Vec<_>>();
+    let mut rocks = vec![2022, 1_000_000_000_000];
+    rocks.extend(
+        args.windows(2)
+            .filter(|opt| opt[0] == "--rocks")
+            .map(|opt| opt[1].parse::<i64>().unwrap()),
+    );
+
+    let num_arg = |name: &str, default: i64| {
+        arg_value(&args, name).map_or(default, |val| val.parse::<i64>().unwrap())
+    };
+    let shapes = match arg_value(&args, "--shapes") {
+        Some(path) => std::fs::read_to_string(path).unwrap(),
+        None => SHAPES.to_string(),
+    };
+    let rules = Rules {
+        width: num_arg("--width", GRID_WIDTH),
+        col_gap: num_arg("--col-gap", COL_GAP),
+        row_gap: num_arg("--row-gap", ROW_GAP),
+        shapes: parse_shapes(&shapes).unwrap(),
+    };
+    rules.check().unwrap();
+
+    let max_rocks = 1_000_000;
+    let cycle = find_cycle(&rules, &moves, max_rocks);
+    match &cycle {
+        Some(cycle) => println!(
+            "Found cycle from rock {}, every {} rocks adds {} height",
+            cycle.start, cycle.length, cycle.gain
+        ),
+        None => println!("No cycle found in {} rocks", max_rocks),
+    }
+
+    for rocks in rocks {
+        match &cycle {
+            Some(cycle) => println!(
+                "Tower height after {} rocks {}",
+                rocks,
+                cycle.height_after(rocks)
+            ),
+            None if rocks <= max_rocks => println!(
+                "Tower height after {} rocks {}",
+                rocks,
+                simulate(&rules, &moves, rocks)
+            ),
+            None => println!("Too many rocks to simulate {}", rocks),
+        }
+    }
+}
 
-    run(&moves, 2022);
-    run(&moves, 1_000_000_000_000);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycle_heights_match_simulation() {
+        let moves = include_str!("../../assets/day17.txt")
+            .trim()
+            .chars()
+            .collect::<Vec<_>>();
+
+        for width in [7, 9, 12] {
+            let rules = Rules {
+                width,
+                col_gap: COL_GAP,
+                row_gap: ROW_GAP,
+                shapes: parse_shapes(SHAPES).unwrap(),
+            };
+            let cycle = find_cycle(&rules, &moves, 100_000).unwrap();
+
+            for rocks in [2022, 12_345, 54_321] {
+                assert_eq!(
+                    cycle.height_after(rocks),
+                    simulate(&rules, &moves, rocks),
+                    "width {} after {} rocks",
+                    width,
+                    rocks
+                );
+            }
+        }
+    }
 }
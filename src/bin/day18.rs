@@ -1,113 +1,259 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 
 /// The input defines the x,y,z coord of 1x1x1 cubes in 3d space
 /// Part A:
 /// Count the number of exposed sides of cubes.
 /// Part B:
 /// Count the number of exposed sides of cubes, excluding cavities.
-
 type Pos = (i32, i32, i32);
 
-fn count_exposed_sides(cubes: &HashSet<Pos>) {
-    let mut count = 0;
+/// The six directions to a neighbouring cube, as the axis and which way along it.
+const SIDES: [(usize, i32); 6] = [(0, 1), (0, -1), (1, 1), (1, -1), (2, 1), (2, -1)];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Voxel {
+    Lava,
+    /// Air the steam outside can get to
+    Steam,
+    /// Air trapped inside the droplet
+    Pocket,
+}
+
+fn axis(pos: Pos, axis: usize) -> i32 {
+    [pos.0, pos.1, pos.2][axis]
+}
+
+fn step(pos: Pos, axis: usize, by: i32) -> Pos {
+    match axis {
+        0 => (pos.0 + by, pos.1, pos.2),
+        1 => (pos.0, pos.1 + by, pos.2),
+        _ => (pos.0, pos.1, pos.2 + by),
+    }
+}
 
-    for (x, y, z) in cubes {
-        let left = (x - 1, *y, *z);
-        let right = (x + 1, *y, *z);
-        let top = (*x, y - 1, *z);
-        let bottom = (*x, y + 1, *z);
-        let forward = (*x, *y, z + 1);
-        let backward = (*x, *y, z - 1);
+/// The droplet as a dense grid of voxels, with a layer of steam all round it.
+struct Droplet {
+    min: Pos,
+    max: Pos,
+    voxels: Vec<Voxel>,
+}
 
-        let checks = [left, right, top, bottom, forward, backward];
+impl Droplet {
+    fn new(cubes: &[Pos]) -> Droplet {
+        // Find bounding box +1
+        let mut min = (i32::MAX, i32::MAX, i32::MAX);
+        let mut max = (i32::MIN, i32::MIN, i32::MIN);
+        for (x, y, z) in cubes {
+            min = (min.0.min(*x - 1), min.1.min(*y - 1), min.2.min(*z - 1));
+            max = (max.0.max(*x + 1), max.1.max(*y + 1), max.2.max(*z + 1));
+        }
+        if cubes.is_empty() {
+            (min, max) = ((0, 0, 0), (0, 0, 0));
+        }
 
-        for check in checks {
-            if !cubes.contains(&check) {
-                count += 1;
+        let size = (max.0 - min.0 + 1) * (max.1 - min.1 + 1) * (max.2 - min.2 + 1);
+        let mut droplet = Droplet {
+            min,
+            max,
+            voxels: vec![Voxel::Pocket; size as usize],
+        };
+        for cube in cubes {
+            let idx = droplet.index(*cube).unwrap();
+            droplet.voxels[idx] = Voxel::Lava;
+        }
+
+        // Now flood fill inside the bounding box, whatever the steam can't reach is a pocket
+        let start = droplet.index(min).unwrap();
+        droplet.voxels[start] = Voxel::Steam;
+        let mut stack = vec![min];
+        while let Some(pos) = stack.pop() {
+            for (axis, by) in SIDES {
+                let next = step(pos, axis, by);
+                if let Some(idx) = droplet.index(next) {
+                    if droplet.voxels[idx] == Voxel::Pocket {
+                        droplet.voxels[idx] = Voxel::Steam;
+                        stack.push(next);
+                    }
+                }
             }
         }
+
+        droplet
     }
 
-    println!("Exposed sides: {}", count);
-}
+    fn index(&self, pos: Pos) -> Option<usize> {
+        let in_box =
+            (0..3).all(|idx| (axis(self.min, idx)..=axis(self.max, idx)).contains(&axis(pos, idx)));
+        if !in_box {
+            return None;
+        }
 
-fn fill_cavities(cubes: &HashSet<Pos>) -> HashSet<Pos> {
-    // Find bounding box +1
-    let mut min = (0, 0, 0);
-    let mut max = (0, 0, 0);
-    for (x, y, z) in cubes {
-        min.0 = min.0.min(*x);
-        min.1 = min.1.min(*y);
-        min.2 = min.2.min(*z);
-
-        max.0 = max.0.max(*x);
-        max.1 = max.1.max(*y);
-        max.2 = max.2.max(*z);
+        let width = (self.max.0 - self.min.0 + 1) as usize;
+        let depth = (self.max.1 - self.min.1 + 1) as usize;
+        let (x, y, z) = (
+            (pos.0 - self.min.0) as usize,
+            (pos.1 - self.min.1) as usize,
+            (pos.2 - self.min.2) as usize,
+        );
+        Some(x + width * (y + depth * z))
     }
 
-    min.0 -= 1;
-    min.1 -= 1;
-    min.2 -= 1;
+    /// Everything outside the bounding box is steam.
+    fn get(&self, pos: Pos) -> Voxel {
+        self.index(pos).map_or(Voxel::Steam, |idx| self.voxels[idx])
+    }
+
+    fn positions(&self) -> impl Iterator<Item = Pos> + '_ {
+        (self.min.2..=self.max.2).flat_map(move |z| {
+            (self.min.1..=self.max.1)
+                .flat_map(move |y| (self.min.0..=self.max.0).map(move |x| (x, y, z)))
+        })
+    }
+
+    /// The sides of lava cubes facing a voxel of the given kind, as the cube and the side.
+    fn faces(&self, facing: &[Voxel]) -> Vec<(Pos, (usize, i32))> {
+        self.positions()
+            .filter(|pos| self.get(*pos) == Voxel::Lava)
+            .flat_map(|pos| SIDES.iter().map(move |side| (pos, *side)))
+            .filter(|(pos, (axis, by))| facing.contains(&self.get(step(*pos, *axis, *by))))
+            .collect()
+    }
 
-    max.0 += 1;
-    max.1 += 1;
-    max.2 += 1;
+    /// Every side of a cube not against another cube, including those facing pockets.
+    fn surface_area(&self) -> usize {
+        self.faces(&[Voxel::Steam, Voxel::Pocket]).len()
+    }
 
-    // Now flood fill inside the bounding box
-    let mut steam = HashSet::new();
-    let mut stack = vec![min];
-    while let Some((x, y, z)) = stack.pop() {
-        steam.insert((x, y, z));
+    fn exterior_area(&self) -> usize {
+        self.faces(&[Voxel::Steam]).len()
+    }
 
-        let left = (x - 1, y, z);
-        let right = (x + 1, y, z);
-        let top = (x, y - 1, z);
-        let bottom = (x, y + 1, z);
-        let forward = (x, y, z + 1);
-        let backward = (x, y, z - 1);
+    /// The volume of each separate pocket of trapped air, largest first.
+    fn cavities(&self) -> Vec<usize> {
+        let mut seen = vec![false; self.voxels.len()];
+        let mut volumes = vec![];
 
-        let moves = [left, right, top, bottom, forward, backward];
+        for pos in self.positions() {
+            let idx = self.index(pos).unwrap();
+            if self.voxels[idx] != Voxel::Pocket || seen[idx] {
+                continue;
+            }
 
-        for mv in moves {
-            if in_bounding_box(&mv, &min, &max) && !steam.contains(&mv) && !cubes.contains(&mv) {
-                stack.push(mv);
+            let mut volume = 0;
+            seen[idx] = true;
+            let mut stack = vec![pos];
+            while let Some(pos) = stack.pop() {
+                volume += 1;
+                for (axis, by) in SIDES {
+                    let next = step(pos, axis, by);
+                    if let Some(idx) = self.index(next) {
+                        if self.voxels[idx] == Voxel::Pocket && !seen[idx] {
+                            seen[idx] = true;
+                            stack.push(next);
+                        }
+                    }
+                }
             }
+
+            volumes.push(volume);
         }
+
+        volumes.sort_unstable_by(|a, b| b.cmp(a));
+        volumes
     }
 
-    // Everything inside the bounding box that isn't a cube and isn't steam is a cavity.
-    // Combine cavity and cubes.
-    let mut filled = cubes.clone();
-    for x in min.0..max.0 {
-        for y in min.1..max.1 {
-            for z in min.2..max.2 {
-                let pos = (x, y, z);
-                if !cubes.contains(&pos) && !steam.contains(&pos) {
-                    filled.insert(pos);
+    /// The corners of each outside face, anticlockwise when looked at from outside.
+    fn exterior_quads(&self) -> Vec<[Pos; 4]> {
+        self.faces(&[Voxel::Steam])
+            .into_iter()
+            .map(|(pos, (side_axis, by))| {
+                let (u, v) = ((side_axis + 1) % 3, (side_axis + 2) % 3);
+                let base = if by > 0 { step(pos, side_axis, 1) } else { pos };
+                let quad = [
+                    base,
+                    step(base, u, 1),
+                    step(step(base, u, 1), v, 1),
+                    step(base, v, 1),
+                ];
+
+                // u x v points along the axis, so flip the winding for faces pointing back
+                if by > 0 {
+                    quad
+                } else {
+                    [quad[0], quad[3], quad[2], quad[1]]
                 }
+            })
+            .collect()
+    }
+
+    /// Wavefront OBJ of the outside surface, sharing the vertices between faces.
+    fn to_obj(&self) -> String {
+        let mut vertices = HashMap::new();
+        let mut out = String::from("# Lava droplet exterior\n");
+        let mut faces = String::new();
+
+        for quad in self.exterior_quads() {
+            let mut corners = vec![];
+            for corner in quad {
+                let next = vertices.len() + 1;
+                let idx = *vertices.entry(corner).or_insert_with(|| {
+                    out += &format!("v {} {} {}\n", corner.0, corner.1, corner.2);
+                    next
+                });
+                corners.push(idx.to_string());
             }
+            faces += &format!("f {}\n", corners.join(" "));
         }
+
+        out + &faces
     }
 
-    filled
-}
+    /// ASCII STL of the outside surface, two triangles per face.
+    fn to_stl(&self) -> String {
+        let mut out = String::from("solid droplet\n");
 
-fn in_bounding_box(pos: &Pos, min: &Pos, max: &Pos) -> bool {
-    if pos.0 < min.0 || pos.0 > max.0 {
-        return false;
-    }
+        for quad in self.exterior_quads() {
+            // The normal is the axis the face is flat on, pointing out
+            let across = (
+                quad[2].0 - quad[0].0,
+                quad[2].1 - quad[0].1,
+                quad[2].2 - quad[0].2,
+            );
+            let along = (
+                quad[1].0 - quad[0].0,
+                quad[1].1 - quad[0].1,
+                quad[1].2 - quad[0].2,
+            );
+            let normal = (
+                along.1 * across.2 - along.2 * across.1,
+                along.2 * across.0 - along.0 * across.2,
+                along.0 * across.1 - along.1 * across.0,
+            );
 
-    if pos.1 < min.1 || pos.1 > max.1 {
-        return false;
-    }
+            for triangle in [[quad[0], quad[1], quad[2]], [quad[0], quad[2], quad[3]]] {
+                out += &format!("  facet normal {} {} {}\n", normal.0, normal.1, normal.2);
+                out += "    outer loop\n";
+                for (x, y, z) in triangle {
+                    out += &format!("      vertex {} {} {}\n", x, y, z);
+                }
+                out += "    endloop\n  endfacet\n";
+            }
+        }
 
-    if pos.2 < min.2 || pos.2 > max.2 {
-        return false;
+        out + "endsolid droplet\n"
     }
+}
 
-    true
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|idx| args.get(idx + 1))
+        .cloned()
 }
 
+/// Options:
+/// --obj FILE   save the outside of the droplet as a Wavefront OBJ mesh
+/// --stl FILE   save the outside of the droplet as an ASCII STL mesh
 fn main() {
     let input = include_str!("../../assets/day18.txt");
 
@@ -121,12 +267,29 @@ fn main() {
 
             (els[0], els[1], els[2])
         })
-        .collect::<HashSet<_>>();
+        .collect::<Vec<_>>();
+
+    let droplet = Droplet::new(&cubes);
 
     // Part A
-    count_exposed_sides(&cubes);
+    println!("Exposed sides: {}", droplet.surface_area());
 
     // Part B
-    let filled = fill_cavities(&cubes);
-    count_exposed_sides(&filled);
+    println!("Exposed sides: {}", droplet.exterior_area());
+
+    let cavities = droplet.cavities();
+    println!(
+        "Cavities: {} holding {} cubes, sizes {:?}",
+        cavities.len(),
+        cavities.iter().sum::<usize>(),
+        cavities
+    );
+
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(path) = arg_value(&args, "--obj") {
+        std::fs::write(path, droplet.to_obj()).unwrap();
+    }
+    if let Some(path) = arg_value(&args, "--stl") {
+        std::fs::write(path, droplet.to_stl()).unwrap();
+    }
 }
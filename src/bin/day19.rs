@@ -13,7 +13,6 @@ use regex::Regex;
 /// Part A:
 /// The quality of a blueprint is id * number of geodes in 24 minutes.
 /// Sum the quality of all blueprints.
-
 const PART_A_DURATION_MINS: i32 = 24;
 const PART_B_DURATION_MINS: i32 = 32;
 const ACTION_MINS: i32 = 1;
@@ -8,8 +8,7 @@ use std::time;
 /// Mix the list then sum the 1000th, 2000th and 3000th numbers after the value 0.
 /// Part B:
 /// Multiply the numbers by 811589153 and mix the list 10 times.
-
-fn mix(list: &mut Vec<(usize, i64)>, count: usize) {
+fn mix(list: &mut [(usize, i64)], count: usize) {
     for _ in 0..count {
         for orig_idx in 0..list.len() {
             let cur_idx = list
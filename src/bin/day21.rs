@@ -9,7 +9,6 @@ use regex::Regex;
 /// The monkey with id "root" is now an equality check.
 /// The monkey with id "humn" is actually you, its number is not relevant.
 /// Find the number for "humn" so that the "root" equality check passes.
-
 type Op = Box<dyn Fn(i64, i64) -> i64>;
 
 struct Monkey {
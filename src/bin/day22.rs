@@ -116,7 +116,7 @@ impl Display for Map {
                     }
                 }
             }
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
 
         Ok(())
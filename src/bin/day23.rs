@@ -52,10 +52,10 @@ impl State<'_> {
                     .enumerate()
                     .filter_map(move |(col_idx, content)| {
                         if content == '#' {
-                            return Some(Pos {
+                            Some(Pos {
                                 row: row_idx as i64,
                                 col: col_idx as i64,
-                            });
+                            })
                         } else {
                             None
                         }
@@ -124,7 +124,7 @@ impl State<'_> {
                                     *clash = true;
                                     update_pos = *first_pos;
                                 }
-                                new_elves.insert(update_pos.clone(), (update_pos, false));
+                                new_elves.insert(update_pos, (update_pos, false));
                             } else {
                                 new_elves.insert(new_pos, (*elf, false));
                                 moved = true;
@@ -136,7 +136,7 @@ impl State<'_> {
                 }
 
                 if !moved {
-                    new_elves.insert(elf.clone(), (*elf, false));
+                    new_elves.insert(*elf, (*elf, false));
                 }
 
                 any_moves |= moved;
@@ -144,7 +144,7 @@ impl State<'_> {
 
             self.elves = new_elves
                 .iter()
-                .filter(|(_k, v)| v.1 == false)
+                .filter(|(_k, v)| !v.1)
                 .map(|(k, _v)| k)
                 .cloned()
                 .collect::<HashSet<_>>();
@@ -219,7 +219,7 @@ fn compute(start_grid: &Grid) -> Vec<Grid> {
     grid_states
 }
 
-fn solve(grid_states: &Vec<Grid>, start: State, end: Pos) -> i64 {
+fn solve(grid_states: &[Grid], start: State, end: Pos) -> i64 {
     let mut queue = vec![start];
     let steps = vec![
         Pos { row: 1, col: 0 },
@@ -243,7 +243,7 @@ fn solve(grid_states: &Vec<Grid>, start: State, end: Pos) -> i64 {
                 continue;
             }
 
-            let grid = &grid_states[(new_state.minute as usize % grid_states.len()) as usize];
+            let grid = &grid_states[new_state.minute as usize % grid_states.len()];
             if grid.find_blizzard(new_state.pos).is_none() && grid.in_grid(new_state.pos) {
                 if new_state.pos == end {
                     grid.print(state.pos);
@@ -12,11 +12,10 @@ use num::Integer;
 /// 8 == 2=
 /// Part A:
 /// Sum all the numbers and provide the result in SNAFU
-
 fn from_snafu(from: &str) -> i64 {
     let mut val = 0;
     for unit in from.chars().rev().enumerate() {
-        let unit_val = (5 as i64).pow(unit.0 as u32);
+        let unit_val = 5_i64.pow(unit.0 as u32);
         let unit_mult = match unit.1 {
             '-' => -1,
             '=' => -2,